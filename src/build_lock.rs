@@ -0,0 +1,58 @@
+//! Cargo's build locks, held while a build writes to a target directory.
+//!
+//! Cargo takes an exclusive file lock on `<profile>/.cargo-lock` for the host target and on
+//! `<triple>/<profile>/.cargo-lock` when cross-compiling.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+const LOCK_FILE: &str = ".cargo-lock";
+
+/// A lock file of `target_dir` held by another process, if there is one.
+pub(crate) fn held_lock(target_dir: &Path) -> Result<Option<PathBuf>> {
+	for path in lock_files(target_dir)? {
+		let file = open(&path)?;
+		match file.try_lock() {
+			// Released when `file` is dropped.
+			Ok(()) => {}
+			Err(TryLockError::WouldBlock) => return Ok(Some(path)),
+			Err(TryLockError::Error(e)) => return Err(e).with_context(|| format!("locking {:?}", path)),
+		}
+	}
+	Ok(None)
+}
+
+/// Blocks until the lock at `path` is released. It is not kept, so a build could start again.
+pub(crate) fn wait_for(path: &Path) -> Result<()> {
+	open(path)?.lock().with_context(|| format!("locking {:?}", path))
+}
+
+fn open(path: &Path) -> Result<File> {
+	OpenOptions::new()
+		.read(true)
+		.open(path)
+		.with_context(|| format!("opening {:?}", path))
+}
+
+fn lock_files(target_dir: &Path) -> io::Result<Vec<PathBuf>> {
+	let mut files = Vec::new();
+	for e in target_dir.read_dir()? {
+		let dir = e?.path();
+		if !dir.is_dir() {
+			continue;
+		}
+		if dir.join(LOCK_FILE).is_file() {
+			files.push(dir.join(LOCK_FILE));
+		}
+		for e in dir.read_dir()? {
+			let sub = e?.path();
+			if sub.join(LOCK_FILE).is_file() {
+				files.push(sub.join(LOCK_FILE));
+			}
+		}
+	}
+	Ok(files)
+}
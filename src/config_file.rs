@@ -0,0 +1,254 @@
+//! Default options read from configuration files.
+//!
+//! The user file, `$XDG_CONFIG_HOME/cargo-clean-recursive/config.toml` (`~/.config` by default),
+//! is read first. A [`LOCAL_FILE`] in the current directory or one of its ancestors, typically
+//! committed at the root of a repository, then overrides it. Its `exclude` and `protect` patterns
+//! and rules are added to the user ones.
+//!
+//! ```toml
+//! depth = 10
+//! exclude = ["vendor"]
+//! older_than = "30d"
+//! incremental = true
+//!
+//! [[rules]]
+//! path = "~/work/prod-service"
+//! never_clean = true
+//! ```
+
+use std::env::var_os;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::units::parse_duration;
+use crate::{Backend, PathRule};
+
+/// Name of the project-local configuration file.
+pub const LOCAL_FILE: &str = ".cargo-clean-recursive.toml";
+
+/// Options of a configuration file, named like the command line flags. Unset ones are `None`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+	pub depth: Option<usize>,
+	pub exclude: Vec<String>,
+	pub respect_ignore: Option<bool>,
+	pub follow_symlinks: Option<bool>,
+	pub one_file_system: Option<bool>,
+	pub older_than: Option<String>,
+	pub jobs: Option<usize>,
+	pub backend: Option<Backend>,
+	/// Relative to the directory of the file, `~` being the home directory.
+	pub protect: Vec<String>,
+	pub trash: Option<bool>,
+	/// Relative to the directory of the file, `~` being the home directory.
+	pub trash_dir: Option<PathBuf>,
+
+	pub doc: Option<bool>,
+	pub release: Option<bool>,
+	pub debug: Option<bool>,
+	pub profile: Option<Vec<String>>,
+	pub target: Option<Vec<String>>,
+	pub all_cross_targets: Option<bool>,
+	pub incremental: Option<bool>,
+	pub unused_for: Option<String>,
+
+	pub rules: Vec<FileRule>,
+}
+
+/// Overrides for the projects under `path`, see [`PathRule`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileRule {
+	/// Relative to the directory of the file, `~` being the home directory.
+	pub path: PathBuf,
+	#[serde(default)]
+	pub never_clean: bool,
+	pub older_than: Option<String>,
+}
+
+impl FileConfig {
+	/// Reads the user file and the local file found from `dir`, if they exist.
+	pub fn load(dir: &Path) -> Result<FileConfig> {
+		let mut config = FileConfig::default();
+		if let Some(file) = user_file().filter(|f| f.is_file()) {
+			config = config.merge(FileConfig::read(&file)?);
+		}
+		if let Some(file) = dir.ancestors().map(|d| d.join(LOCAL_FILE)).find(|f| f.is_file()) {
+			config = config.merge(FileConfig::read(&file)?);
+		}
+		Ok(config)
+	}
+
+	/// Reads `file`, resolving the paths of its rules.
+	pub fn read(file: &Path) -> Result<FileConfig> {
+		let content = fs::read_to_string(file).with_context(|| format!("reading {:?}", file))?;
+		let mut config: FileConfig = toml::from_str(&content).with_context(|| format!("parsing {:?}", file))?;
+		let dir = file.parent().unwrap_or_else(|| Path::new(""));
+		for rule in &mut config.rules {
+			rule.path = resolve(dir, &rule.path);
+		}
+		if let Some(trash_dir) = &mut config.trash_dir {
+			*trash_dir = resolve(dir, trash_dir);
+		}
+		for protect in &mut config.protect {
+			*protect = resolve(dir, Path::new(protect)).to_string_lossy().into_owned();
+		}
+		Ok(config)
+	}
+
+	/// `other` options override these ones, its excludes, protections and rules are added to these ones.
+	pub fn merge(mut self, other: FileConfig) -> FileConfig {
+		// Delete modes are taken as a whole, mixing them would be surprising.
+		if other.has_delete_mode() {
+			self.doc = other.doc;
+			self.release = other.release;
+			self.debug = other.debug;
+			self.profile = other.profile;
+			self.target = other.target;
+			self.all_cross_targets = other.all_cross_targets;
+			self.incremental = other.incremental;
+			self.unused_for = other.unused_for;
+		}
+
+		let mut exclude = self.exclude;
+		exclude.extend(other.exclude);
+		let mut protect = self.protect;
+		protect.extend(other.protect);
+		let mut rules = self.rules;
+		rules.extend(other.rules);
+
+		FileConfig {
+			depth: other.depth.or(self.depth),
+			exclude,
+			respect_ignore: other.respect_ignore.or(self.respect_ignore),
+			follow_symlinks: other.follow_symlinks.or(self.follow_symlinks),
+			one_file_system: other.one_file_system.or(self.one_file_system),
+			older_than: other.older_than.or(self.older_than),
+			jobs: other.jobs.or(self.jobs),
+			backend: other.backend.or(self.backend),
+			trash: other.trash.or(self.trash),
+			trash_dir: other.trash_dir.or(self.trash_dir),
+			protect,
+			rules,
+			..self
+		}
+	}
+
+	/// Whether a delete mode option is set.
+	pub fn has_delete_mode(&self) -> bool {
+		self.doc.is_some()
+			|| self.release.is_some()
+			|| self.debug.is_some()
+			|| self.profile.is_some()
+			|| self.target.is_some()
+			|| self.all_cross_targets.is_some()
+			|| self.incremental.is_some()
+			|| self.unused_for.is_some()
+	}
+
+	/// Rules to give to [`ConfigBuilder::rules`](crate::ConfigBuilder::rules).
+	pub fn path_rules(&self) -> Result<Vec<PathRule>> {
+		self.rules
+			.iter()
+			.map(|r| {
+				let older_than = match &r.older_than {
+					Some(d) => Some(parse_duration(d).with_context(|| format!("parsing '{}' as duration", d))?),
+					None => None,
+				};
+				Ok(PathRule {
+					path: r.path.clone(),
+					never_clean: r.never_clean,
+					older_than,
+				})
+			})
+			.collect()
+	}
+}
+
+fn user_file() -> Option<PathBuf> {
+	let config_dir = match var_os("XDG_CONFIG_HOME") {
+		Some(dir) => PathBuf::from(dir),
+		None => home()?.join(".config"),
+	};
+	Some(config_dir.join("cargo-clean-recursive").join("config.toml"))
+}
+
+fn home() -> Option<PathBuf> {
+	var_os("HOME").or_else(|| var_os("USERPROFILE")).map(PathBuf::from)
+}
+
+/// `path` relative to `dir`, with a leading `~` expanded.
+fn resolve(dir: &Path, path: &Path) -> PathBuf {
+	let mut components = path.components();
+	match components.next() {
+		Some(Component::Normal(c)) if c == "~" => match home() {
+			Some(home) => home.join(components.as_path()),
+			None => path.to_path_buf(),
+		},
+		_ => dir.join(path),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn merge_overrides_options_and_adds_lists() {
+		let user = FileConfig {
+			depth: Some(3),
+			jobs: Some(4),
+			exclude: vec!["vendor".into()],
+			protect: vec!["/a".into()],
+			release: Some(true),
+			incremental: Some(true),
+			..FileConfig::default()
+		};
+		let local = FileConfig {
+			depth: Some(10),
+			exclude: vec!["node_modules".into()],
+			protect: vec!["/b".into()],
+			..FileConfig::default()
+		};
+		let merged = user.merge(local);
+		assert_eq!(merged.depth, Some(10));
+		assert_eq!(merged.jobs, Some(4));
+		assert_eq!(merged.exclude, ["vendor", "node_modules"]);
+		assert_eq!(merged.protect, ["/a", "/b"]);
+		assert_eq!(merged.release, Some(true));
+		assert_eq!(merged.incremental, Some(true));
+	}
+
+	#[test]
+	fn merge_replaces_delete_mode_as_a_whole() {
+		let user = FileConfig {
+			release: Some(true),
+			incremental: Some(true),
+			..FileConfig::default()
+		};
+		let local = FileConfig {
+			doc: Some(true),
+			..FileConfig::default()
+		};
+		let merged = user.merge(local);
+		assert_eq!(merged.doc, Some(true));
+		assert_eq!(merged.release, None);
+		assert_eq!(merged.incremental, None);
+	}
+
+	#[test]
+	fn resolves_paths_from_the_file() {
+		let dir = Path::new("/repo");
+		assert_eq!(resolve(dir, Path::new("work")), Path::new("/repo/work"));
+		assert_eq!(resolve(dir, Path::new("/abs")), Path::new("/abs"));
+		assert_eq!(resolve(dir, Path::new("~foo")), Path::new("/repo/~foo"));
+		if let Some(home) = home() {
+			assert_eq!(resolve(dir, Path::new("~/work")), home.join("work"));
+			assert_eq!(resolve(dir, Path::new("~")), home);
+		}
+	}
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+/// Identifies a directory regardless of the path it is reached through.
+#[cfg(unix)]
+pub(crate) type DirId = (u64, u64);
+#[cfg(not(unix))]
+pub(crate) type DirId = std::path::PathBuf;
+
+#[cfg(unix)]
+pub(crate) fn dir_id(path: &Path) -> Result<DirId> {
+	use std::os::unix::fs::MetadataExt;
+
+	let meta = fs::metadata(path)?;
+	Ok((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn dir_id(path: &Path) -> Result<DirId> {
+	Ok(path.canonicalize()?)
+}
+
+/// Device `path` lives on, following symlinks. Always `None` where unsupported.
+#[cfg(unix)]
+pub(crate) fn device(path: &Path) -> Result<Option<u64>> {
+	use std::os::unix::fs::MetadataExt;
+
+	Ok(Some(fs::metadata(path)?.dev()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn device(_path: &Path) -> Result<Option<u64>> {
+	Ok(None)
+}
+
+/// Total size of the files under `path`, not following symlinks. Missing directories count as empty,
+/// and a file counts as itself.
+pub(crate) fn dir_size(path: &Path) -> Result<u64> {
+	let meta = match fs::symlink_metadata(path) {
+		Ok(meta) => meta,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+		Err(e) => return Err(e.into()),
+	};
+	if !meta.is_dir() {
+		return Ok(meta.len());
+	}
+
+	let mut size = 0;
+	for e in path.read_dir()? {
+		let e = e?;
+		let meta = e.metadata()?;
+		if meta.is_dir() {
+			size += dir_size(&e.path())?;
+		} else {
+			size += meta.len();
+		}
+	}
+	Ok(size)
+}
+
+/// Size and newest modification time of a directory tree.
+pub(crate) struct DirStats {
+	pub(crate) size: u64,
+	pub(crate) modified: SystemTime,
+}
+
+/// Measures the tree under `path`, not following symlinks. `path` itself counts towards the modification time.
+pub(crate) fn dir_stats(path: &Path) -> Result<DirStats> {
+	let mut stats = DirStats {
+		size: 0,
+		modified: path.symlink_metadata()?.modified()?,
+	};
+	for e in path.read_dir()? {
+		let e = e?;
+		let meta = e.metadata()?;
+		let (size, modified) = if meta.is_dir() {
+			let sub = dir_stats(&e.path())?;
+			(sub.size, sub.modified)
+		} else {
+			(meta.len(), meta.modified()?)
+		};
+		stats.size += size;
+		stats.modified = stats.modified.max(modified);
+	}
+	Ok(stats)
+}
@@ -0,0 +1,60 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+/// Ignore file dedicated to this tool, always honored.
+pub const IGNORE_FILE: &str = ".cleanrecursiveignore";
+
+/// Ignore files of the directories being walked, innermost first.
+pub(crate) struct IgnoreStack<'a> {
+	matcher: Gitignore,
+	parent: Option<&'a IgnoreStack<'a>>,
+}
+
+impl<'a> IgnoreStack<'a> {
+	pub(crate) fn new(matcher: Gitignore, parent: Option<&'a IgnoreStack<'a>>) -> Self {
+		IgnoreStack { matcher, parent }
+	}
+}
+
+/// Whether the directory `path` is ignored, the innermost matching ignore file deciding.
+pub(crate) fn is_ignored(stack: Option<&IgnoreStack>, path: &Path) -> bool {
+	let mut stack = stack;
+	while let Some(s) = stack {
+		match s.matcher.matched(path, true) {
+			Match::Ignore(_) => return true,
+			Match::Whitelist(_) => return false,
+			Match::None => stack = s.parent,
+		}
+	}
+	false
+}
+
+/// Loads the ignore files of `dir`: `.cleanrecursiveignore`, plus `.gitignore` and `.ignore`
+/// if `respect_ignore` is set. Returns `None` if there are none.
+pub(crate) fn load(dir: &Path, respect_ignore: bool) -> Result<Option<Gitignore>> {
+	let names: &[&str] = if respect_ignore {
+		&[".gitignore", ".ignore", IGNORE_FILE]
+	} else {
+		&[IGNORE_FILE]
+	};
+
+	let mut builder = GitignoreBuilder::new(dir);
+	let mut found = false;
+	for name in names {
+		let file = dir.join(name);
+		if file.is_file() {
+			found = true;
+			if let Some(e) = builder.add(&file) {
+				return Err(e).with_context(|| format!("reading {:?}", file));
+			}
+		}
+	}
+
+	if !found {
+		return Ok(None);
+	}
+	Ok(Some(builder.build()?))
+}
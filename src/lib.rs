@@ -0,0 +1,1215 @@
+//! Finds cargo projects under a directory and cleans their build artifacts.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use cargo_clean_recursive::{Cleaner, Config, DeleteMode, Event};
+//!
+//! let config = Config::builder().delete_mode(DeleteMode::All).dry_run(true).build();
+//! let cleaner = Cleaner::new(config, |event| {
+//!     if let Event::Project(report) = event {
+//!         println!("{:?}: {:?} bytes", report.path, report.bytes_before);
+//!     }
+//! })?;
+//! cleaner.process_dir(Path::new("."))?;
+//! println!("{} bytes", cleaner.summary().freed);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+mod build_lock;
+pub mod config_file;
+mod disk;
+mod ignore_files;
+mod report;
+pub mod sweep;
+mod target_dir;
+pub mod trash;
+pub mod units;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::iter;
+use std::path::{self, Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use glob::{MatchOptions, Pattern};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+use build_lock::{held_lock, wait_for};
+use disk::{device, dir_id, dir_size, dir_stats, DirId};
+pub use ignore_files::IGNORE_FILE;
+use ignore_files::{is_ignored, IgnoreStack};
+pub use report::{CommandReport, Event, ProjectReport, Summary};
+use target_dir::{normalize, resolve_target_dir, workspace_members};
+use units::format_duration;
+
+/// Name of the marker file that keeps the project containing it from being cleaned.
+pub const PROTECT_FILE: &str = ".no-clean-recursive";
+
+/// Settings of a cleaning run, created with [`Config::builder`].
+#[derive(Debug, Clone)]
+pub struct Config {
+	depth: usize,
+	exclude_dirs: Vec<String>,
+	exclude: Vec<String>,
+	respect_ignore: bool,
+	follow_symlinks: bool,
+	one_file_system: bool,
+	del_mode: DeleteMode,
+	backend: Backend,
+	dry_run: bool,
+	older_than: Option<Duration>,
+	jobs: usize,
+	rules: Vec<PathRule>,
+	protect: Vec<String>,
+	wait_for_lock: bool,
+	trash: Option<PathBuf>,
+}
+
+impl Config {
+	pub fn builder() -> ConfigBuilder {
+		ConfigBuilder {
+			config: Config {
+				depth: 64,
+				exclude_dirs: Vec::new(),
+				exclude: Vec::new(),
+				respect_ignore: false,
+				follow_symlinks: false,
+				one_file_system: false,
+				del_mode: DeleteMode::All,
+				backend: Backend::Cargo,
+				dry_run: false,
+				older_than: None,
+				jobs: 0,
+				rules: Vec::new(),
+				protect: Vec::new(),
+				wait_for_lock: false,
+				trash: None,
+			},
+		}
+	}
+}
+
+/// Builder for [`Config`]. Defaults match the command line tool.
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+	config: Config,
+}
+
+impl ConfigBuilder {
+	/// Recursive search depth limit, 64 by default.
+	pub fn depth(mut self, depth: usize) -> Self {
+		self.config.depth = depth;
+		self
+	}
+
+	/// Directories whose name ends with one of these are not searched.
+	pub fn exclude_dirs(mut self, exclude_dirs: Vec<String>) -> Self {
+		self.config.exclude_dirs = exclude_dirs;
+		self
+	}
+
+	/// Gitignore-style patterns of directories not to search, relative to the searched directory.
+	pub fn exclude(mut self, exclude: Vec<String>) -> Self {
+		self.config.exclude = exclude;
+		self
+	}
+
+	/// Also honors `.gitignore` and `.ignore` files, on top of [`IGNORE_FILE`].
+	pub fn respect_ignore(mut self, respect_ignore: bool) -> Self {
+		self.config.respect_ignore = respect_ignore;
+		self
+	}
+
+	/// Searches symlinked directories too, visiting each directory once.
+	pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+		self.config.follow_symlinks = follow_symlinks;
+		self
+	}
+
+	/// Doesn't search directories on other filesystems than the searched directory (Unix only).
+	pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+		self.config.one_file_system = one_file_system;
+		self
+	}
+
+	pub fn delete_mode(mut self, del_mode: DeleteMode) -> Self {
+		self.config.del_mode = del_mode;
+		self
+	}
+
+	pub fn backend(mut self, backend: Backend) -> Self {
+		self.config.backend = backend;
+		self
+	}
+
+	/// Only reports what would be cleaned.
+	pub fn dry_run(mut self, dry_run: bool) -> Self {
+		self.config.dry_run = dry_run;
+		self
+	}
+
+	/// Skips targets modified more recently than this.
+	pub fn older_than(mut self, older_than: Option<Duration>) -> Self {
+		self.config.older_than = older_than;
+		self
+	}
+
+	/// Number of directories processed in parallel, 0 for one per CPU.
+	pub fn jobs(mut self, jobs: usize) -> Self {
+		self.config.jobs = jobs;
+		self
+	}
+
+	/// Overrides for the projects under given paths.
+	pub fn rules(mut self, rules: Vec<PathRule>) -> Self {
+		self.config.rules = rules;
+		self
+	}
+
+	/// Paths or glob patterns of projects never to clean, along with the projects under them.
+	/// Projects are matched by their canonical path and by their target directory.
+	///
+	/// Relative ones are relative to the current directory. Projects containing a [`PROTECT_FILE`]
+	/// are never cleaned either.
+	pub fn protect(mut self, protect: Vec<String>) -> Self {
+		self.config.protect = protect;
+		self
+	}
+
+	/// Waits for the builds running in a target directory to finish instead of skipping it.
+	pub fn wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+		self.config.wait_for_lock = wait_for_lock;
+		self
+	}
+
+	/// Moves what would be deleted to this trash directory instead, or to the trash at the top of
+	/// their filesystem for projects on another one, see [`trash`].
+	///
+	/// The paths are moved directly even with [`Backend::Cargo`], since `cargo clean` deletes them.
+	pub fn trash(mut self, trash: Option<PathBuf>) -> Self {
+		self.config.trash = trash;
+		self
+	}
+
+	pub fn build(self) -> Config {
+		self.config
+	}
+}
+
+/// Settings for the projects under `path`, overriding the [`Config`] ones.
+///
+/// When several rules match a project, the one with the longest `path` applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRule {
+	pub path: PathBuf,
+	/// Leaves the projects alone.
+	pub never_clean: bool,
+	/// Replaces [`ConfigBuilder::older_than`] if set.
+	pub older_than: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+	/// Runs `cargo clean` in each project.
+	Cargo,
+	/// Removes the directories `cargo clean` would remove, without spawning cargo.
+	Native,
+}
+
+/// What to delete from the target directory of each project.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteMode {
+	All,
+	Partial {
+		doc: bool,
+		/// Profile names as given to cargo, e.g. `dev`, `release` or `bench`.
+		profiles: Vec<String>,
+		/// Target triples whose whole `target/<triple>` directory is deleted, if it holds cross builds.
+		targets: Vec<String>,
+		/// Deletes the directories of every target triple, keeping the host build.
+		all_cross_targets: bool,
+		/// Deletes the incremental compilation caches of every profile.
+		incremental: bool,
+	},
+	/// Deletes the artifacts built by other toolchains than the ones whose
+	/// [`rustc_hash`](sweep::rustc_hash) is listed.
+	Sweep {
+		rustc_hashes: Vec<u64>,
+	},
+	/// Deletes the dependencies whose artifacts weren't used for `unused_for`, keeping the rest
+	/// of the build.
+	Unused {
+		unused_for: Duration,
+	},
+}
+
+impl DeleteMode {
+	/// Fails on profile names and target triples that would resolve outside of the target directory.
+	fn check(&self) -> Result<()> {
+		if let DeleteMode::Partial { profiles, targets, .. } = self {
+			for profile in profiles {
+				// The rule cargo enforces on profile names.
+				if profile.is_empty()
+					|| !profile
+						.chars()
+						.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+				{
+					bail!(
+						"invalid profile name '{}', expected only letters, numbers, `-` and `_`",
+						profile
+					);
+				}
+			}
+			for target in targets {
+				check_dir_name(target).with_context(|| format!("invalid target triple '{}'", target))?;
+			}
+		}
+		Ok(())
+	}
+
+	fn cargo_args(&self) -> Vec<Vec<&str>> {
+		match self {
+			DeleteMode::All => vec![vec!["clean"]],
+			DeleteMode::Sweep { .. } | DeleteMode::Unused { .. } => Vec::new(),
+			DeleteMode::Partial { doc, profiles, .. } => {
+				let mut args = Vec::new();
+				if *doc {
+					args.push(vec!["clean", "--doc"]);
+				}
+				for profile in profiles {
+					if profile == "release" {
+						args.push(vec!["clean", "--release"]);
+					} else {
+						args.push(vec!["clean", "--profile", profile.as_str()]);
+					}
+				}
+				args
+			}
+		}
+	}
+
+	/// Directories `cargo clean` removes with this mode, for the host target.
+	fn native_paths(&self, target_dir: &Path) -> Vec<PathBuf> {
+		match self {
+			DeleteMode::All => vec![target_dir.to_path_buf()],
+			DeleteMode::Sweep { .. } | DeleteMode::Unused { .. } => Vec::new(),
+			DeleteMode::Partial { doc, profiles, .. } => {
+				let mut paths = Vec::new();
+				if *doc {
+					paths.push(target_dir.join("doc"));
+				}
+				for profile in profiles {
+					paths.push(target_dir.join(profile_dir(profile)));
+				}
+				paths
+			}
+		}
+	}
+
+	/// Paths to delete that `cargo clean` can't remove on their own: cross-compilation
+	/// directories, incremental caches, swept and unused artifacts.
+	fn direct_paths(&self, target_dir: &Path) -> Result<Vec<PathBuf>> {
+		let (targets, all_cross_targets, incremental) = match self {
+			DeleteMode::All => return Ok(Vec::new()),
+			DeleteMode::Sweep { rustc_hashes } => return sweep::stale_paths(target_dir, rustc_hashes),
+			DeleteMode::Unused { unused_for } => {
+				let cutoff = SystemTime::now().checked_sub(*unused_for).unwrap_or(UNIX_EPOCH);
+				return sweep::unused_paths(target_dir, cutoff);
+			}
+			DeleteMode::Partial {
+				targets,
+				all_cross_targets,
+				incremental,
+				..
+			} => (targets, *all_cross_targets, *incremental),
+		};
+
+		let mut paths = Vec::new();
+		for target in targets {
+			// Keeps the host build even if a profile directory is given as a triple, e.g. `--target debug`.
+			let dir = target_dir.join(target);
+			if is_cross_target_dir(&dir)? {
+				paths.push(dir);
+			}
+		}
+		if all_cross_targets {
+			for e in target_dir.read_dir()? {
+				let dir = e?.path();
+				if is_cross_target_dir(&dir)? && !paths.contains(&dir) {
+					paths.push(dir);
+				}
+			}
+		}
+		if incremental {
+			paths.extend(incremental_dirs(target_dir)?);
+		}
+		Ok(paths)
+	}
+}
+
+/// Fails unless `name` is a single path component, which can't escape the directory it is joined to.
+fn check_dir_name(name: &str) -> Result<()> {
+	let mut components = Path::new(name).components();
+	match (components.next(), components.next()) {
+		(Some(Component::Normal(c)), None) if c == name && !name.contains(['/', '\\']) => Ok(()),
+		_ => bail!("expected a plain name, without path separators"),
+	}
+}
+
+/// Whether `dir` holds builds for a target triple, i.e. contains profile directories.
+/// Host profile directories such as `target/debug` hold `.fingerprint` themselves instead.
+fn is_cross_target_dir(dir: &Path) -> Result<bool> {
+	if !dir.is_dir() {
+		return Ok(false);
+	}
+	for e in dir.read_dir()? {
+		if e?.path().join(".fingerprint").is_dir() {
+			return Ok(true);
+		}
+	}
+	Ok(false)
+}
+
+/// `incremental` directories of the host profiles (`target/<profile>/incremental`) and of the
+/// cross-compilation profiles (`target/<triple>/<profile>/incremental`).
+fn incremental_dirs(target_dir: &Path) -> Result<Vec<PathBuf>> {
+	let mut dirs = Vec::new();
+	for e in target_dir.read_dir()? {
+		let dir = e?.path();
+		if !dir.is_dir() {
+			continue;
+		}
+		if dir.join("incremental").is_dir() {
+			dirs.push(dir.join("incremental"));
+		}
+		for e in dir.read_dir()? {
+			let sub = e?.path().join("incremental");
+			if sub.is_dir() {
+				dirs.push(sub);
+			}
+		}
+	}
+	Ok(dirs)
+}
+
+/// Name of the directory cargo builds `profile` into.
+fn profile_dir(profile: &str) -> &str {
+	match profile {
+		"dev" | "test" => "debug",
+		"bench" => "release",
+		_ => profile,
+	}
+}
+
+/// Settings of a walk specific to the searched directory.
+struct Root {
+	excludes: Gitignore,
+	/// Device of the searched directory, set when staying on one filesystem.
+	device: Option<u64>,
+}
+
+/// Searches directories for cargo projects and cleans them, reporting progress through `on_event`.
+///
+/// State is kept across calls, so a target directory shared by several projects is only cleaned once
+/// per `Cleaner`.
+pub struct Cleaner<F> {
+	config: Config,
+	on_event: F,
+	pool: ThreadPool,
+	/// Target directories already handled, so that a target shared between projects is cleaned once.
+	cleaned: Mutex<HashSet<PathBuf>>,
+	/// Member directories of the workspaces found so far, which are cleaned along with their workspace root.
+	workspace_members: Mutex<HashSet<PathBuf>>,
+	/// Directories walked from the current root when following symlinks, to break loops.
+	visited: Mutex<HashSet<DirId>>,
+	/// Project directories already handled, reached again when searched directories are nested.
+	handled: Mutex<HashSet<PathBuf>>,
+	/// Compiled [`ConfigBuilder::protect`] patterns.
+	protect: Vec<Pattern>,
+	/// Target directories of the protected projects found so far, with why they are protected.
+	protected_targets: Mutex<HashMap<PathBuf, String>>,
+	freed: AtomicU64,
+	failed: Mutex<Vec<PathBuf>>,
+}
+
+impl<F: Fn(Event) + Sync> Cleaner<F> {
+	/// `on_event` is called from worker threads as projects are processed.
+	pub fn new(mut config: Config, on_event: F) -> Result<Self> {
+		// Matched against canonical project paths.
+		for rule in &mut config.rules {
+			if let Ok(path) = fs::canonicalize(&rule.path) {
+				rule.path = path;
+			}
+		}
+		config.del_mode.check()?;
+		let protect = config
+			.protect
+			.iter()
+			.map(|p| {
+				let path = path::absolute(p).with_context(|| format!("resolving {:?}", p))?;
+				match fs::canonicalize(&path) {
+					// An existing path is taken literally, even if it looks like a pattern.
+					Ok(path) => Ok(Pattern::new(&Pattern::escape(&path.to_string_lossy()))?),
+					Err(_) => Pattern::new(&path.to_string_lossy()).with_context(|| format!("parsing pattern '{}'", p)),
+				}
+			})
+			.collect::<Result<_>>()?;
+		let pool = ThreadPoolBuilder::new()
+			.num_threads(config.jobs)
+			.build()
+			.context("starting worker threads")?;
+		Ok(Cleaner {
+			config,
+			on_event,
+			pool,
+			cleaned: Default::default(),
+			workspace_members: Default::default(),
+			visited: Default::default(),
+			handled: Default::default(),
+			protect,
+			protected_targets: Default::default(),
+			freed: AtomicU64::new(0),
+			failed: Default::default(),
+		})
+	}
+
+	/// Cleans the projects under `path`, up to the configured depth.
+	///
+	/// Only errors reading `path` itself are returned, the others are reported as [`Event::Warning`].
+	pub fn process_dir(&self, path: &Path) -> Result<()> {
+		// Walked from its canonical form, like workspace members and protections are.
+		self.process_dirs(&[path.to_path_buf()])
+	}
+
+	/// Cleans the projects under each of `paths`.
+	///
+	/// A path inside another one is still searched on its own, since the depth limit or the
+	/// exclusions may keep the walk of the outer one from reaching it. Projects reached twice are
+	/// handled once. Projects are cleaned once the search is over, when the target directories of
+	/// all the protected ones are known.
+	pub fn process_dirs(&self, paths: &[PathBuf]) -> Result<()> {
+		let mut roots = Vec::new();
+		for path in paths {
+			let path = path.canonicalize().with_context(|| format!("resolving {:?}", path))?;
+			if !roots.iter().any(|(p, _)| p == &path) {
+				let root = self.root(&path)?;
+				roots.push((path, root));
+			}
+		}
+
+		self.pool.install(|| {
+			let found = Mutex::new(Vec::new());
+			for (path, root) in &roots {
+				self.visited.lock().unwrap().clear();
+				self.walk(path, self.config.depth, root, None, &found)?;
+			}
+			found.into_inner().unwrap().par_iter().for_each(|path| {
+				if let Err(e) = self.detect_and_clean(path) {
+					self.emit(Event::Warning(e));
+				}
+			});
+			Ok(())
+		})
+	}
+
+	/// Cleans `path` if it is a cargo project with a target directory.
+	pub fn detect_and_clean(&self, path: &Path) -> Result<()> {
+		match self.clean_dir(path) {
+			Ok(freed) => {
+				self.freed.fetch_add(freed, Ordering::Relaxed);
+				Ok(())
+			}
+			Err(e) => {
+				self.failed.lock().unwrap().push(path.to_path_buf());
+				Err(e.context(format!("cleaning directory {:?}", path)))
+			}
+		}
+	}
+
+	/// Totals of everything processed so far.
+	pub fn summary(&self) -> Summary {
+		Summary {
+			freed: self.freed.load(Ordering::Relaxed),
+			failed: self.failed.lock().unwrap().clone(),
+		}
+	}
+
+	fn emit(&self, event: Event) {
+		(self.on_event)(event)
+	}
+
+	fn root(&self, path: &Path) -> Result<Root> {
+		Ok(Root {
+			excludes: self.excludes(path)?,
+			device: if self.config.one_file_system {
+				device(path).with_context(|| format!("reading metadata of {:?}", path))?
+			} else {
+				None
+			},
+		})
+	}
+
+	/// Matcher for the exclude patterns, relative to `root`.
+	fn excludes(&self, root: &Path) -> Result<Gitignore> {
+		let mut builder = GitignoreBuilder::new(root);
+		for pattern in &self.config.exclude {
+			builder
+				.add_line(None, pattern)
+				.with_context(|| format!("invalid exclude pattern '{}'", pattern))?;
+		}
+		Ok(builder.build()?)
+	}
+
+	/// Adds the projects under `path` to `found`, recording the targets of the protected ones.
+	fn walk(
+		&self,
+		path: &Path,
+		depth: usize,
+		root: &Root,
+		ignores: Option<&IgnoreStack>,
+		found: &Mutex<Vec<PathBuf>>,
+	) -> Result<()> {
+		if depth == 0 {
+			return Ok(());
+		}
+
+		if self.config.follow_symlinks && !self.visited.lock().unwrap().insert(dir_id(path)?) {
+			return Ok(());
+		}
+
+		// A broken manifest is reported by `cargo clean` itself, keep traversing as if it had no workspace.
+		let members = workspace_members(path).unwrap_or_default();
+		if path.join("Cargo.toml").exists() {
+			self.record_protected(path, &members);
+			found.lock().unwrap().push(path.to_path_buf());
+		}
+		self.workspace_members.lock().unwrap().extend(members);
+
+		let stack;
+		let ignores = match ignore_files::load(path, self.config.respect_ignore) {
+			Ok(Some(matcher)) => {
+				stack = IgnoreStack::new(matcher, ignores);
+				Some(&stack)
+			}
+			Ok(None) => ignores,
+			Err(e) => {
+				self.emit(Event::Warning(e));
+				ignores
+			}
+		};
+
+		let mut children = self.subdirs(path, root, ignores)?;
+		{
+			let members = self.workspace_members.lock().unwrap();
+			children.retain(|c| !members.contains(&normalize(c)));
+		}
+
+		children.par_iter().for_each(|child| {
+			if let Err(e) = self.walk(child, depth - 1, root, ignores, found) {
+				self.emit(Event::Warning(e));
+			}
+		});
+
+		Ok(())
+	}
+
+	/// Records the target directories of the protected ones among the project at `path` and its
+	/// workspace `members`, which aren't walked, so that projects sharing them are left alone too.
+	fn record_protected(&self, path: &Path, members: &[PathBuf]) {
+		for project in iter::once(path).chain(members.iter().map(PathBuf::as_path)) {
+			if let Some(reason) = self.protection(project) {
+				// A missing target has nothing to protect.
+				if let Ok(target_dir) = resolve_target_dir(project).and_then(|t| Ok(t.canonicalize()?)) {
+					let reason = format!("target {:?} is shared with {:?}, {}", target_dir, project, reason);
+					self.protected_targets.lock().unwrap().insert(target_dir, reason);
+				}
+			}
+		}
+	}
+
+	/// Subdirectories of `path` to search, leaving out the excluded and ignored ones.
+	fn subdirs(&self, path: &Path, root: &Root, ignores: Option<&IgnoreStack>) -> Result<Vec<PathBuf>> {
+		let mut children = Vec::new();
+		for e in path
+			.read_dir()
+			.with_context(|| format!("reading directory {:?}", path.canonicalize()))?
+		{
+			let e = e?;
+			let file_type = e.file_type()?;
+			let is_dir = if file_type.is_symlink() && self.config.follow_symlinks {
+				e.path().is_dir()
+			} else {
+				file_type.is_dir()
+			};
+			if is_dir
+				&& !root.excludes.matched(e.path(), true).is_ignore()
+				&& !is_ignored(ignores, &e.path())
+				&& !self.config.exclude_dirs.iter().any(|d| {
+					e.file_name()
+						.as_os_str()
+						.to_str()
+						.is_some_and(|e| e.ends_with(d.as_str()))
+				}) {
+				if root.device.is_some() && device(&e.path())? != root.device {
+					continue;
+				}
+				children.push(e.path());
+			}
+		}
+		Ok(children)
+	}
+
+	/// Why the project at `path` must not be cleaned, if it is protected.
+	fn protection(&self, path: &Path) -> Option<String> {
+		if path.join(PROTECT_FILE).exists() {
+			return Some(format!("protected by {}", PROTECT_FILE));
+		}
+		// The patterns are canonical, reaching the project through a symlink or `.` mustn't get around them.
+		self.protected_by(&path.canonicalize().unwrap_or_else(|_| path.to_path_buf()))
+	}
+
+	/// The [`ConfigBuilder::protect`] pattern matching `path` or one of its ancestors, if any.
+	fn protected_by(&self, path: &Path) -> Option<String> {
+		let options = MatchOptions {
+			require_literal_separator: true,
+			..MatchOptions::new()
+		};
+		self.protect
+			.iter()
+			.find(|p| path.ancestors().any(|a| p.matches_path_with(a, options)))
+			.map(|p| format!("protected by '{}'", p))
+	}
+
+	/// Returns the number of bytes freed, or that would be freed on a dry run.
+	fn clean_dir(&self, path: &Path) -> Result<u64> {
+		if !path.join("Cargo.toml").exists() {
+			return Ok(0);
+		}
+		if !self.handled.lock().unwrap().insert(normalize(path)) {
+			return Ok(0);
+		}
+
+		let target_dir = resolve_target_dir(path).context("resolving target directory")?;
+		if !target_dir.exists() || !target_dir.is_dir() {
+			return Ok(0);
+		}
+
+		let mut report = ProjectReport {
+			path: path.to_path_buf(),
+			target_dir: target_dir.canonicalize()?,
+			delete_mode: self.config.del_mode.clone(),
+			backend: self.config.backend,
+			dry_run: self.config.dry_run,
+			skipped: None,
+			bytes_before: None,
+			bytes_after: None,
+			last_modified: None,
+			commands: Vec::new(),
+			removed: Vec::new(),
+			warning: None,
+		};
+
+		let result = self.clean_project(&mut report);
+		if let Err(e) = &result {
+			report.warning = Some(format!("{:#}", e));
+		}
+		self.emit(Event::Project(Box::new(report)));
+		result
+	}
+
+	/// Cleans the detected project described by `report`, filling it in along the way.
+	fn clean_project(&self, report: &mut ProjectReport) -> Result<u64> {
+		let path = report.path.clone();
+		let target_dir = report.target_dir.clone();
+
+		if let Some(reason) = self.protection(&path) {
+			report.skipped = Some(reason);
+			return Ok(0);
+		}
+		// Members aren't walked, and the workspace target holds their builds too.
+		for member in workspace_members(&path).unwrap_or_default() {
+			if let Some(reason) = self.protection(&member) {
+				report.skipped = Some(format!("workspace member {:?} is {}", member, reason));
+				return Ok(0);
+			}
+		}
+		if let Some(reason) = self.protected_by(&target_dir) {
+			report.skipped = Some(format!("target {:?} is {}", target_dir, reason));
+			return Ok(0);
+		}
+		if let Some(reason) = self.protected_targets.lock().unwrap().get(&target_dir) {
+			report.skipped = Some(reason.clone());
+			return Ok(0);
+		}
+
+		// Rules are canonical, like protections they apply however the project is reached.
+		let canonical = path.canonicalize()?;
+		let rule = self
+			.config
+			.rules
+			.iter()
+			.filter(|r| canonical.starts_with(&r.path))
+			.max_by_key(|r| r.path.components().count());
+		if let Some(rule) = rule.filter(|r| r.never_clean) {
+			report.skipped = Some(format!("never cleaned by the rule for {:?}", rule.path));
+			return Ok(0);
+		}
+
+		if !self.cleaned.lock().unwrap().insert(target_dir.clone()) {
+			report.skipped = Some(format!(
+				"target {:?} is shared with a project already processed",
+				target_dir
+			));
+			return Ok(0);
+		}
+
+		// Cleaning under a running build would corrupt it.
+		while let Some(lock) = held_lock(&target_dir)? {
+			if !self.config.wait_for_lock {
+				report.skipped = Some(format!("a build is running ({:?} is locked)", lock));
+				return Ok(0);
+			}
+			if self.config.dry_run {
+				break;
+			}
+			self.emit(Event::Waiting {
+				path: path.clone(),
+				lock: lock.clone(),
+			});
+			wait_for(&lock)?;
+		}
+
+		let stats = dir_stats(&target_dir).with_context(|| format!("measuring {:?}", target_dir))?;
+		let before = stats.size;
+		report.bytes_before = Some(before);
+		report.last_modified = Some(stats.modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+
+		if let Some(older_than) = rule.and_then(|r| r.older_than).or(self.config.older_than) {
+			let age = SystemTime::now().duration_since(stats.modified).unwrap_or_default();
+			if age < older_than {
+				report.skipped = Some(format!("target modified {} ago", format_duration(age)));
+				return Ok(0);
+			}
+		}
+
+		let native = self.config.backend == Backend::Native || self.config.trash.is_some();
+		let cargo_args = if native {
+			Vec::new()
+		} else {
+			self.config.del_mode.cargo_args()
+		};
+		let mut paths = if native {
+			self.config.del_mode.native_paths(&target_dir)
+		} else {
+			Vec::new()
+		};
+		paths.extend(self.config.del_mode.direct_paths(&target_dir)?);
+		let paths = removal_roots(paths);
+
+		if self.config.dry_run {
+			// List what would be done, without exit statuses.
+			for args in &cargo_args {
+				report.commands.push(CommandReport {
+					args: args.iter().map(|a| a.to_string()).collect(),
+					exit_status: None,
+				});
+			}
+			// `cargo clean` removes the same directories as the native backend.
+			let removed = if cargo_args.is_empty() {
+				paths.clone()
+			} else {
+				removal_roots(
+					paths
+						.iter()
+						.cloned()
+						.chain(self.config.del_mode.native_paths(&target_dir))
+						.collect(),
+				)
+			};
+			let mut freed = 0;
+			for path in &removed {
+				freed += dir_size(path).with_context(|| format!("measuring {:?}", path))?;
+			}
+			let freed = freed.min(before);
+
+			report.removed = paths;
+			report.bytes_after = Some(before - freed);
+			return Ok(freed);
+		}
+
+		self.emit(Event::Cleaning { path });
+
+		run_cargo_clean(report, &cargo_args)?;
+		remove_paths(report, paths, self.config.trash.as_deref())?;
+
+		let after = dir_size(&target_dir).with_context(|| format!("measuring {:?}", target_dir))?;
+		report.bytes_after = Some(after);
+
+		Ok(before.saturating_sub(after))
+	}
+}
+
+/// The existing `paths`, sorted and deduplicated, leaving out the ones inside another since they go with it.
+fn removal_roots(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+	paths.retain(|p| p.exists());
+	paths.sort();
+	paths.dedup();
+	let all = paths.clone();
+	paths.retain(|p| !all.iter().any(|other| other != p && p.starts_with(other)));
+	paths
+}
+
+/// Whether `path` lies strictly under `dir`, without going through `..`.
+fn is_inside(path: &Path, dir: &Path) -> bool {
+	path.strip_prefix(dir).is_ok_and(|rest| {
+		rest.components().next().is_some() && rest.components().all(|c| matches!(c, Component::Normal(_)))
+	})
+}
+
+fn run_cargo_clean(report: &mut ProjectReport, cargo_args: &[Vec<&str>]) -> Result<()> {
+	for args in cargo_args {
+		let output = Command::new("cargo").args(args).current_dir(&report.path).output()?;
+		report.commands.push(CommandReport {
+			args: args.iter().map(|a| a.to_string()).collect(),
+			exit_status: output.status.code(),
+		});
+		if !output.status.success() {
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			return Err(anyhow!("{}", stderr.trim())).context(format!(
+				"`cargo {}` failed ({})",
+				args.join(" "),
+				output.status
+			));
+		}
+	}
+	Ok(())
+}
+
+fn remove_paths(report: &mut ProjectReport, paths: Vec<PathBuf>, trash: Option<&Path>) -> Result<()> {
+	// A target directory containing the project (e.g. `target-dir = "."`) would take the sources with it.
+	if report.path.canonicalize()?.starts_with(&report.target_dir) {
+		bail!("target {:?} contains the project itself", report.target_dir);
+	}
+	// Checked before touching anything, so that nothing is removed if one of them is wrong.
+	// Only a full clean removes the target directory itself.
+	let whole_target = report.delete_mode == DeleteMode::All;
+	if let Some(path) = paths
+		.iter()
+		.find(|p| !(is_inside(p, &report.target_dir) || whole_target && *p == &report.target_dir))
+	{
+		bail!(
+			"refusing to remove {:?}, outside of target {:?}",
+			path,
+			report.target_dir
+		);
+	}
+
+	for path in paths {
+		if let Some(trash) = trash {
+			if !path.exists() {
+				continue;
+			}
+			trash::move_to(trash, &path)?;
+		} else if path.is_dir() {
+			fs::remove_dir_all(&path).with_context(|| format!("removing {:?}", path))?;
+		} else if path.exists() {
+			fs::remove_file(&path).with_context(|| format!("removing {:?}", path))?;
+		} else {
+			continue;
+		}
+		report.removed.push(path);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn new_report(project: &Path, target_dir: &Path, delete_mode: DeleteMode) -> ProjectReport {
+		ProjectReport {
+			path: project.to_path_buf(),
+			target_dir: target_dir.to_path_buf(),
+			delete_mode,
+			backend: Backend::Native,
+			dry_run: false,
+			skipped: None,
+			bytes_before: None,
+			bytes_after: None,
+			last_modified: None,
+			commands: Vec::new(),
+			removed: Vec::new(),
+			warning: None,
+		}
+	}
+
+	fn write_package(dir: &Path) {
+		let name = dir.file_name().unwrap().to_str().unwrap();
+		fs::create_dir_all(dir.join("src")).unwrap();
+		fs::write(
+			dir.join("Cargo.toml"),
+			format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+		)
+		.unwrap();
+		fs::write(dir.join("src").join("lib.rs"), "").unwrap();
+	}
+
+	/// Reports of the projects found by `process_dir(path)`.
+	fn process(config: Config, path: &Path) -> Vec<ProjectReport> {
+		let reports = Mutex::new(Vec::new());
+		let cleaner = Cleaner::new(config, |event| {
+			if let Event::Project(report) = event {
+				reports.lock().unwrap().push(*report);
+			}
+		})
+		.unwrap();
+		cleaner.process_dir(path).unwrap();
+		reports.into_inner().unwrap()
+	}
+
+	fn partial(targets: &[&str]) -> DeleteMode {
+		DeleteMode::Partial {
+			doc: false,
+			profiles: Vec::new(),
+			targets: targets.iter().map(|t| t.to_string()).collect(),
+			all_cross_targets: false,
+			incremental: false,
+		}
+	}
+
+	#[test]
+	fn rejects_target_triples_escaping_the_target_dir() {
+		for target in [
+			"/tmp/outside",
+			"..",
+			".",
+			"",
+			"a/b",
+			"a\\b",
+			"x86_64-unknown-linux-gnu/",
+		] {
+			assert!(partial(&[target]).check().is_err(), "{:?} accepted", target);
+		}
+		assert!(partial(&["x86_64-unknown-linux-gnu", "wasm32-unknown-unknown"])
+			.check()
+			.is_ok());
+	}
+
+	#[test]
+	fn target_triples_keep_the_host_build() {
+		let tmp = tempfile::tempdir().unwrap();
+		let target_dir = tmp.path().canonicalize().unwrap();
+		let cross = target_dir.join("wasm32-unknown-unknown");
+		for profile in [
+			target_dir.join("debug"),
+			target_dir.join("release"),
+			cross.join("debug"),
+		] {
+			fs::create_dir_all(profile.join(".fingerprint")).unwrap();
+		}
+		fs::create_dir_all(target_dir.join("doc")).unwrap();
+
+		let mode = partial(&[
+			"debug",
+			"release",
+			"doc",
+			"wasm32-unknown-unknown",
+			"x86_64-unknown-linux-gnu",
+		]);
+		assert_eq!(mode.direct_paths(&target_dir).unwrap(), [cross]);
+	}
+
+	#[test]
+	fn removes_nested_paths_once() {
+		let tmp = tempfile::tempdir().unwrap();
+		let target_dir = tmp.path().canonicalize().unwrap();
+		let (debug, release) = (target_dir.join("debug"), target_dir.join("release"));
+		fs::create_dir_all(debug.join("incremental")).unwrap();
+		fs::create_dir_all(&release).unwrap();
+
+		let paths = vec![
+			release.clone(),
+			debug.join("incremental"),
+			debug.clone(),
+			release.clone(),
+			target_dir.join("doc"),
+		];
+		assert_eq!(removal_roots(paths), [debug, release]);
+	}
+
+	#[test]
+	fn rejects_invalid_profile_names() {
+		for profile in ["../..", "..", ".", "", "a/b", "/tmp", "release "] {
+			let mode = DeleteMode::Partial {
+				doc: false,
+				profiles: vec![profile.to_string()],
+				targets: Vec::new(),
+				all_cross_targets: false,
+				incremental: false,
+			};
+			assert!(mode.check().is_err(), "{:?} accepted", profile);
+		}
+		let mode = DeleteMode::Partial {
+			doc: false,
+			profiles: vec!["dev".to_string(), "release-lto".to_string(), "my_profile".to_string()],
+			targets: Vec::new(),
+			all_cross_targets: false,
+			incremental: false,
+		};
+		assert!(mode.check().is_ok());
+	}
+
+	#[test]
+	fn protected_member_protects_its_workspace() {
+		let tmp = tempfile::tempdir().unwrap();
+		let ws = tmp.path().canonicalize().unwrap().join("ws");
+		write_package(&ws.join("m"));
+		fs::create_dir_all(ws.join("target").join("debug")).unwrap();
+		fs::write(ws.join("Cargo.toml"), "[workspace]\nmembers = [\"m\"]\n").unwrap();
+		fs::write(ws.join("m").join(PROTECT_FILE), "").unwrap();
+
+		let reports = process(Config::builder().backend(Backend::Native).build(), tmp.path());
+
+		assert!(ws.join("target").join("debug").is_dir());
+		assert!(!reports.is_empty() && reports.iter().all(|r| r.skipped.is_some()));
+	}
+
+	#[test]
+	fn protected_project_protects_its_shared_target() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path().canonicalize().unwrap();
+		fs::create_dir_all(root.join(".cargo")).unwrap();
+		fs::write(
+			root.join(".cargo").join("config.toml"),
+			"[build]\ntarget-dir = \"shared\"\n",
+		)
+		.unwrap();
+		fs::create_dir_all(root.join("shared").join("debug")).unwrap();
+		for name in ["a", "b", "c"] {
+			write_package(&root.join(name));
+		}
+		fs::write(root.join("b").join(PROTECT_FILE), "").unwrap();
+
+		let reports = process(Config::builder().backend(Backend::Native).build(), &root);
+
+		assert!(root.join("shared").join("debug").is_dir());
+		assert_eq!(reports.len(), 3);
+		assert!(reports.iter().all(|r| r.skipped.is_some()));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn protection_follows_symlinks() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path().canonicalize().unwrap();
+		let (project, links) = (root.join("a"), root.join("links"));
+		write_package(&project);
+		fs::create_dir_all(project.join("target").join("debug")).unwrap();
+		fs::create_dir_all(&links).unwrap();
+		std::os::unix::fs::symlink(&project, links.join("a2")).unwrap();
+
+		let config = |protect: &Path| {
+			Config::builder()
+				.backend(Backend::Native)
+				.follow_symlinks(true)
+				.protect(vec![protect.to_string_lossy().into_owned()])
+				.build()
+		};
+		for protect in [project.clone(), project.join("target")] {
+			let reports = process(config(&protect), &links);
+			assert!(project.join("target").join("debug").is_dir());
+			assert_eq!(reports.len(), 1);
+			assert!(reports[0].skipped.is_some(), "{:?} not protected", protect);
+		}
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn rules_follow_symlinks() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path().canonicalize().unwrap();
+		let (project, links) = (root.join("a"), root.join("links"));
+		write_package(&project);
+		fs::create_dir_all(project.join("target").join("debug")).unwrap();
+		fs::create_dir_all(&links).unwrap();
+		std::os::unix::fs::symlink(&project, links.join("a2")).unwrap();
+
+		let config = Config::builder()
+			.backend(Backend::Native)
+			.follow_symlinks(true)
+			.rules(vec![PathRule {
+				path: project.clone(),
+				never_clean: true,
+				older_than: None,
+			}])
+			.build();
+		let reports = process(config, &links);
+
+		assert!(project.join("target").join("debug").is_dir());
+		assert_eq!(reports.len(), 1);
+		assert!(reports[0].skipped.is_some());
+	}
+
+	#[test]
+	fn relative_roots_skip_workspace_members() {
+		// Relative to the package root, where tests run.
+		fs::create_dir_all("target").unwrap();
+		let tmp = tempfile::tempdir_in("target").unwrap();
+		let ws = tmp.path().join("ws");
+		write_package(&ws.join("crates").join("m"));
+		fs::create_dir_all(ws.join("target").join("debug")).unwrap();
+		fs::write(ws.join("Cargo.toml"), "[workspace]\nmembers = [\"crates/*\"]\n").unwrap();
+
+		let config = Config::builder().dry_run(true).build();
+		let relative = tmp.path().strip_prefix(std::env::current_dir().unwrap()).unwrap();
+		let reports = process(config, &Path::new(".").join(relative));
+
+		assert_eq!(reports.len(), 1);
+		assert_eq!(reports[0].path, ws.canonicalize().unwrap());
+	}
+
+	#[test]
+	fn removal_never_leaves_the_target_dir() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path().canonicalize().unwrap();
+		let project = root.join("project");
+		let target_dir = project.join("target");
+		let outside = root.join("outside");
+		fs::create_dir_all(target_dir.join("debug")).unwrap();
+		fs::create_dir_all(&outside).unwrap();
+
+		let escaping = [
+			outside.clone(),
+			target_dir.join("..").join("..").join("outside"),
+			target_dir.join("debug").join(".."),
+		];
+		for path in escaping {
+			let mut report = new_report(&project, &target_dir, partial(&[]));
+			let paths = vec![target_dir.join("debug"), path.clone()];
+			assert!(remove_paths(&mut report, paths, None).is_err(), "{:?} removed", path);
+			assert!(report.removed.is_empty());
+		}
+		// Only a full clean removes the target directory itself.
+		let mut report = new_report(&project, &target_dir, partial(&[]));
+		assert!(remove_paths(&mut report, vec![target_dir.clone()], None).is_err());
+		assert!(outside.is_dir() && target_dir.join("debug").is_dir());
+
+		let mut report = new_report(&project, &target_dir, partial(&[]));
+		remove_paths(&mut report, vec![target_dir.join("debug")], None).unwrap();
+		assert!(!target_dir.join("debug").exists() && target_dir.is_dir());
+
+		let mut report = new_report(&project, &target_dir, DeleteMode::All);
+		remove_paths(&mut report, vec![target_dir.clone()], None).unwrap();
+		assert!(!target_dir.exists() && project.is_dir() && outside.is_dir());
+	}
+}
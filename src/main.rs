@@ -1,9 +1,22 @@
+use std::cmp::Reverse;
 use std::env::{args, current_dir};
-use std::path::{Path, PathBuf};
-use std::process::{exit, Command};
+use std::fmt::Write;
+use std::io::{stdin, BufRead};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
-use clap::{App, Arg};
+use anyhow::{bail, Context, Error, Result};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Serialize;
+
+use cargo_clean_recursive::config_file::FileConfig;
+use cargo_clean_recursive::sweep::{installed_toolchains, rustc_hash};
+use cargo_clean_recursive::trash;
+use cargo_clean_recursive::units::{format_duration, format_size, parse_duration, parse_size};
+use cargo_clean_recursive::{Backend, Cleaner, Config, DeleteMode, Event, ProjectReport};
 
 fn main() {
 	if let Err(e) = _main() {
@@ -21,6 +34,12 @@ fn _main() -> Result<()> {
 		args.remove(1);
 	}
 
+	let trash_dir_arg = Arg::with_name("trash_dir")
+		.long("trash-dir")
+		.takes_value(true)
+		.value_name("DIR")
+		.help("Trash directory to use [default: the home trash, ~/.local/share/Trash]");
+
 	let matches = App::new("cargo clean-recursive")
 		.bin_name("cargo clean-recursive")
 		.arg(Arg::with_name("doc").short("d").long("doc").help("Deletes documents"))
@@ -30,120 +49,675 @@ fn _main() -> Result<()> {
 				.long("release")
 				.help("Deletes release target"),
 		)
+		.arg(
+			Arg::with_name("debug")
+				.long("debug")
+				.help("Deletes debug target, built with the dev profile"),
+		)
+		.arg(
+			Arg::with_name("profile")
+				.long("profile")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1)
+				.value_name("NAME")
+				.help("Deletes the target of this profile, e.g. bench or a custom one"),
+		)
+		.arg(
+			Arg::with_name("target")
+				.long("target")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1)
+				.value_name("TRIPLE")
+				.help("Deletes everything built for this target triple"),
+		)
+		.arg(
+			Arg::with_name("all_cross_targets")
+				.long("all-cross-targets")
+				.help("Deletes everything built for target triples, keeping the host build"),
+		)
+		.arg(
+			Arg::with_name("incremental")
+				.long("incremental")
+				.help("Deletes incremental compilation caches, keeping compiled dependencies"),
+		)
+		.arg(
+			Arg::with_name("sweep")
+				.long("sweep")
+				.conflicts_with_all(&[
+					"doc",
+					"release",
+					"debug",
+					"profile",
+					"target",
+					"all_cross_targets",
+					"incremental",
+				])
+				.help("Deletes artifacts built by other toolchains than the installed ones"),
+		)
+		.arg(
+			Arg::with_name("toolchain")
+				.long("toolchain")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1)
+				.value_name("NAME")
+				.requires("sweep")
+				.help("Toolchain whose artifacts --sweep keeps, instead of the installed ones"),
+		)
+		.arg(
+			Arg::with_name("unused_for")
+				.long("unused-for")
+				.takes_value(true)
+				.value_name("DURATION")
+				.conflicts_with_all(&[
+					"doc",
+					"release",
+					"debug",
+					"profile",
+					"target",
+					"all_cross_targets",
+					"incremental",
+					"sweep",
+				])
+				.help("Deletes only the dependencies not used for DURATION (e.g. 30d), keeping the rest of the build"),
+		)
 		.arg(
 			Arg::with_name("depth")
 				.long("depth")
-				.default_value("64")
-				.help("Recursive serarch depth limit"),
+				.takes_value(true)
+				.help("Recursive serarch depth limit [default: 64]"),
+		)
+		.arg(
+			Arg::with_name("path")
+				.short("p")
+				.long("path")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1)
+				.help("Target directory, same as a positional PATH"),
+		)
+		.arg(
+			Arg::with_name("paths")
+				.multiple(true)
+				.value_name("PATH")
+				.help("Directories to search [default: current directory]"),
+		)
+		.arg(
+			Arg::with_name("dry_run")
+				.short("n")
+				.long("dry-run")
+				.help("Lists projects that would be cleaned without cleaning them"),
 		)
-		.arg(Arg::with_name("path").short("p").long("path").help("Target directory"))
 		.arg(
 			Arg::with_name("exclude_dirs")
 				.short("ed")
 				.long("exclude_dirs")
+				.takes_value(true)
 				.help("Exclude directories"),
 		)
+		.arg(
+			Arg::with_name("exclude")
+				.long("exclude")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1)
+				.value_name("PATTERN")
+				.help("Skips directories matching this gitignore-style pattern, relative to the searched directory"),
+		)
+		.arg(
+			Arg::with_name("protect")
+				.long("protect")
+				.takes_value(true)
+				.multiple(true)
+				.number_of_values(1)
+				.value_name("PATH|GLOB")
+				.help("Never cleans the projects matching this path or glob pattern, nor the ones under them"),
+		)
+		.arg(
+			Arg::with_name("respect_ignore")
+				.long("respect-ignore")
+				.help("Skips directories ignored by .gitignore and .ignore files"),
+		)
+		.arg(
+			Arg::with_name("follow_symlinks")
+				.long("follow-symlinks")
+				.help("Searches symlinked directories too"),
+		)
+		.arg(
+			Arg::with_name("one_file_system")
+				.long("one-file-system")
+				.help("Doesn't search directories on other filesystems"),
+		)
+		.arg(
+			Arg::with_name("interactive")
+				.short("i")
+				.long("interactive")
+				.help("Lists detected projects first and lets you choose which ones to clean"),
+		)
+		.arg(
+			Arg::with_name("reclaim")
+				.long("reclaim")
+				.takes_value(true)
+				.value_name("SIZE")
+				.conflicts_with_all(&["interactive", "trash", "trash_dir"])
+				.help("Cleans the biggest and stalest projects first, until SIZE (e.g. 20G) is freed"),
+		)
+		.arg(
+			Arg::with_name("wait_for_lock")
+				.long("wait-for-lock")
+				.help("Waits for running builds to finish instead of skipping their targets"),
+		)
+		.arg(
+			Arg::with_name("older_than")
+				.long("older-than")
+				.takes_value(true)
+				.value_name("DURATION")
+				.help("Only cleans targets untouched for at least DURATION (e.g. 30d, 12h)"),
+		)
+		.arg(
+			Arg::with_name("jobs")
+				.short("j")
+				.long("jobs")
+				.takes_value(true)
+				.value_name("N")
+				.help("Number of directories processed in parallel [default: number of CPUs]"),
+		)
+		.arg(
+			Arg::with_name("format")
+				.long("format")
+				.takes_value(true)
+				.possible_values(&["text", "json", "ndjson"])
+				.default_value("text")
+				.help("Also prints a record per detected project to stdout as JSON or newline-delimited JSON"),
+		)
+		.arg(
+			Arg::with_name("backend")
+				.long("backend")
+				.takes_value(true)
+				.possible_values(&["cargo", "native"])
+				.help("Runs `cargo clean`, or removes the same directories directly without cargo [default: cargo]"),
+		)
+		.arg(
+			Arg::with_name("no_config")
+				.long("no-config")
+				.help("Ignores the configuration files"),
+		)
+		.arg(
+			Arg::with_name("trash")
+				.long("trash")
+				.help("Moves what would be deleted to the trash instead, implies --backend native"),
+		)
+		.arg(
+			trash_dir_arg.clone().help(
+				"Moves what would be deleted to this directory instead, following the freedesktop.org trash layout",
+			),
+		)
+		.subcommand(
+			SubCommand::with_name("restore")
+				.about("Moves the target of a project back from the trash")
+				.arg(
+					Arg::with_name("path")
+						.value_name("PATH")
+						.help("Project whose target to restore [default: current directory]"),
+				)
+				.arg(trash_dir_arg),
+		)
 		.get_matches_from(&args);
 
-	let del_mode = match (matches.is_present("doc"), matches.is_present("release")) {
-		(false, false) => DeleteMode::All,
-		(doc, release) => DeleteMode::Partial { doc, release },
+	let file = if matches.is_present("no_config") {
+		FileConfig::default()
+	} else {
+		FileConfig::load(&current_dir().context("getting current_dir")?)?
+	};
+	let trash_dir = |matches: &ArgMatches| -> Result<PathBuf> {
+		matches
+			.value_of("trash_dir")
+			.map(PathBuf::from)
+			.or_else(|| file.trash_dir.clone())
+			.or_else(trash::default_dir)
+			.context("no home directory to find the trash in, use --trash-dir")
+	};
+
+	if let Some(matches) = matches.subcommand_matches("restore") {
+		let project = match matches.value_of("path") {
+			Some(path) => PathBuf::from(path),
+			None => current_dir().context("getting current_dir")?,
+		};
+		let result = trash::restore(&trash_dir(matches)?, &project)?;
+		for path in &result.restored {
+			eprintln!("Restored {:?}", path);
+		}
+		if !result.existing.is_empty() {
+			let mut msg =
+				"Left in the trash, these paths exist again, move them aside and restore again:\n".to_string();
+			for path in &result.existing {
+				msg.push_str(&format!("\t{:?}\n", path));
+			}
+			eprint!("{}", msg);
+		}
+		return Ok(());
+	}
+
+	// Delete mode flags replace the configured delete mode as a whole.
+	let cli_mode = [
+		"doc",
+		"release",
+		"debug",
+		"profile",
+		"target",
+		"all_cross_targets",
+		"incremental",
+		"sweep",
+		"unused_for",
+	]
+	.iter()
+	.any(|a| matches.is_present(a));
+	let flag = |name: &str, configured: Option<bool>| {
+		if cli_mode {
+			matches.is_present(name)
+		} else {
+			configured.unwrap_or(false)
+		}
+	};
+	let values = |name: &str, configured: &Option<Vec<String>>| -> Vec<String> {
+		if cli_mode {
+			matches
+				.values_of(name)
+				.into_iter()
+				.flatten()
+				.map(String::from)
+				.collect()
+		} else {
+			configured.clone().unwrap_or_default()
+		}
 	};
 
-	let depth_str = matches.value_of("depth").expect("'depth' should be exists");
-	let depth: usize = depth_str
-		.parse()
-		.with_context(|| format!("parsing '{}' as number", depth_str))?;
+	let doc = flag("doc", file.doc);
+	let mut profiles: Vec<String> = Vec::new();
+	if flag("debug", file.debug) {
+		profiles.push("dev".to_string());
+	}
+	if flag("release", file.release) {
+		profiles.push("release".to_string());
+	}
+	profiles.extend(values("profile", &file.profile));
+	let targets = values("target", &file.target);
+	let all_cross_targets = flag("all_cross_targets", file.all_cross_targets);
+	let incremental = flag("incremental", file.incremental);
+	let unused_for = if cli_mode {
+		matches.value_of("unused_for")
+	} else {
+		file.unused_for.as_deref()
+	};
+	let del_mode = if matches.is_present("sweep") {
+		let toolchains: Vec<String> = match matches.values_of("toolchain") {
+			Some(toolchains) => toolchains.map(String::from).collect(),
+			None => installed_toolchains()?,
+		};
+		let rustc_hashes = if toolchains.is_empty() {
+			vec![rustc_hash(None)?]
+		} else {
+			toolchains.iter().map(|t| rustc_hash(Some(t))).collect::<Result<_>>()?
+		};
+		DeleteMode::Sweep { rustc_hashes }
+	} else if let Some(unused_for) = unused_for {
+		DeleteMode::Unused {
+			unused_for: parse_duration(unused_for).with_context(|| format!("parsing '{}' as duration", unused_for))?,
+		}
+	} else if doc || !profiles.is_empty() || !targets.is_empty() || all_cross_targets || incremental {
+		DeleteMode::Partial {
+			doc,
+			profiles,
+			targets,
+			all_cross_targets,
+			incremental,
+		}
+	} else {
+		DeleteMode::All
+	};
 
-	let path = if let Some(path) = matches.value_of("path") {
-		PathBuf::from(path)
+	let depth = if let Some(depth) = matches.value_of("depth") {
+		depth
+			.parse()
+			.with_context(|| format!("parsing '{}' as number", depth))?
 	} else {
-		current_dir().context("getting current_dir")?
+		file.depth.unwrap_or(64)
 	};
 
+	let mut paths: Vec<PathBuf> = matches
+		.values_of("paths")
+		.into_iter()
+		.flatten()
+		.chain(matches.values_of("path").into_iter().flatten())
+		.map(PathBuf::from)
+		.collect();
+	if paths.is_empty() {
+		paths.push(current_dir().context("getting current_dir")?);
+	}
+
 	let exclude_dirs = if let Some(exclude_dirs) = matches.value_of("exclude_dirs") {
-		exclude_dirs.split(' ').collect::<Vec<_>>()
+		exclude_dirs.split(' ').map(String::from).collect::<Vec<_>>()
 	} else {
 		Default::default()
 	};
 
-	process_dir(Path::new(&path), depth, &Config { exclude_dirs, del_mode })?;
+	let mut exclude = file.exclude.clone();
+	exclude.extend(matches.values_of("exclude").into_iter().flatten().map(String::from));
 
-	Ok(())
-}
+	let trash = if matches.is_present("trash") || matches.is_present("trash_dir") || file.trash.unwrap_or(false) {
+		Some(trash_dir(&matches)?)
+	} else {
+		None
+	};
 
-struct Config<'s> {
-	exclude_dirs: Vec<&'s str>,
-	del_mode: DeleteMode,
-}
+	let mut protect = file.protect.clone();
+	protect.extend(matches.values_of("protect").into_iter().flatten().map(String::from));
 
-#[derive(Debug)]
-enum DeleteMode {
-	All,
-	Partial { doc: bool, release: bool },
-}
+	let dry_run = matches.is_present("dry_run");
 
-fn process_dir(path: &Path, depth: usize, config: &Config) -> Result<()> {
-	if depth == 0 {
-		return Ok(());
+	let older_than = if let Some(older_than) = matches.value_of("older_than").or(file.older_than.as_deref()) {
+		Some(parse_duration(older_than).with_context(|| format!("parsing '{}' as duration", older_than))?)
+	} else {
+		None
+	};
+
+	let reclaim = if let Some(reclaim) = matches.value_of("reclaim") {
+		Some(parse_size(reclaim).with_context(|| format!("parsing '{}' as size", reclaim))?)
+	} else {
+		None
+	};
+	// Moving to a trash on the same filesystem frees nothing.
+	if reclaim.is_some() && trash.is_some() {
+		bail!("--reclaim can't free space with `trash = true` from the configuration file, use --no-config");
 	}
 
-	detect_and_clean(path, &config.del_mode).with_context(|| format!("cleaning directory {:?}", path))?;
+	let jobs = if let Some(jobs) = matches.value_of("jobs") {
+		jobs.parse().with_context(|| format!("parsing '{}' as number", jobs))?
+	} else {
+		file.jobs.unwrap_or(0)
+	};
 
-	for e in path
-		.read_dir()
-		.with_context(|| format!("reading directory {:?}", path.canonicalize()))?
-	{
-		let e = e?;
-		if e.file_type()?.is_dir()
-			&& config
-				.exclude_dirs
-				.iter()
-				.find(|&&d| e.file_name().as_os_str().to_str().map_or(false, |e| e.ends_with(d)))
-				.is_none()
-		{
-			if let Err(e) = process_dir(&e.path(), depth - 1, config) {
-				eprintln!("Warn: {}", e);
-				for c in e.chain().skip(1) {
-					eprintln!("	at: {}", c);
-				}
+	let format = match matches.value_of("format").expect("'format' should be exists") {
+		"json" => Format::Json,
+		"ndjson" => Format::Ndjson,
+		_ => Format::Text,
+	};
+
+	let backend = match matches.value_of("backend") {
+		Some("native") => Backend::Native,
+		Some(_) => Backend::Cargo,
+		None => file.backend.unwrap_or(Backend::Cargo),
+	};
+
+	let config = Config::builder()
+		.depth(depth)
+		.exclude_dirs(exclude_dirs)
+		.exclude(exclude)
+		.respect_ignore(matches.is_present("respect_ignore") || file.respect_ignore.unwrap_or(false))
+		.follow_symlinks(matches.is_present("follow_symlinks") || file.follow_symlinks.unwrap_or(false))
+		.one_file_system(matches.is_present("one_file_system") || file.one_file_system.unwrap_or(false))
+		.delete_mode(del_mode)
+		.backend(backend)
+		.dry_run(dry_run)
+		.older_than(older_than)
+		.jobs(jobs)
+		.rules(file.path_rules()?)
+		.protect(protect)
+		.wait_for_lock(matches.is_present("wait_for_lock"))
+		.trash(trash.clone());
+
+	// Records collected for `Format::Json`, printed all at once at the end.
+	let records = Mutex::new(Vec::new());
+	let output = |record: Record| match format {
+		Format::Text => {}
+		Format::Json => records.lock().unwrap().push(record),
+		Format::Ndjson => println!("{}", serde_json::to_string(&record).expect("serializing record")),
+	};
+	let warning = |e: Error| {
+		warn(&e);
+		output(Record::Warning {
+			warning: format!("{:#}", e),
+		});
+	};
+	let cleaner = Cleaner::new(config.clone().build(), |event| match event {
+		Event::Cleaning { path } => eprintln!("Cleaning {:?}", path),
+		Event::Waiting { path, .. } => eprintln!("Waiting for the build of {:?} to finish", path),
+		Event::Project(report) => {
+			print_report(&report, trash.is_some());
+			output(Record::Project(report));
+		}
+		Event::Warning(e) => warning(e),
+		_ => {}
+	})?;
+	if matches.is_present("interactive") {
+		let candidates = scan(config.dry_run(true).build(), &paths, &warning)?;
+		for path in select(&candidates)? {
+			if let Err(e) = cleaner.detect_and_clean(&path) {
+				warning(e);
+			}
+		}
+	} else if let Some(goal) = reclaim {
+		let mut candidates = scan(config.dry_run(true).build(), &paths, &warning)?;
+		rank_for_reclaim(&mut candidates);
+		for candidate in &candidates {
+			if cleaner.summary().freed >= goal {
+				break;
 			}
+			if let Err(e) = cleaner.detect_and_clean(&candidate.path) {
+				warning(e);
+			}
+		}
+		if cleaner.summary().freed < goal {
+			eprintln!(
+				"Warn: nothing left to clean before reclaiming the requested {}",
+				format_size(goal)
+			);
 		}
+	} else {
+		cleaner.process_dirs(&paths)?;
+	}
+	let summary = cleaner.summary();
+
+	if let Format::Json = format {
+		println!("{}", serde_json::to_string_pretty(&records.into_inner().unwrap())?);
+	}
+
+	if dry_run {
+		eprintln!(
+			"Would clean {} of target directories in total",
+			format_size(summary.freed)
+		);
+	} else if trash.is_some() {
+		eprintln!(
+			"Moved {} to the trash in total, `cargo clean-recursive restore PATH` puts a project's target back",
+			format_size(summary.freed)
+		);
+	} else {
+		eprintln!("Freed {} in total", format_size(summary.freed));
+	}
+
+	if !summary.failed.is_empty() {
+		eprintln!("Failed to clean:");
+		for path in &summary.failed {
+			eprintln!("	{:?}", path);
+		}
+		bail!("failed to clean {} project(s)", summary.failed.len());
 	}
 
 	Ok(())
 }
 
-fn detect_and_clean(path: &Path, del_mode: &DeleteMode) -> Result<()> {
-	if !path.join("Cargo.toml").exists() {
-		return Ok(());
+#[derive(Clone, Copy)]
+enum Format {
+	Text,
+	Json,
+	Ndjson,
+}
+
+/// An entry of the `--format json` and `--format ndjson` output.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Record {
+	Project(Box<ProjectReport>),
+	/// A problem outside of any project, e.g. an unreadable directory.
+	Warning {
+		warning: String,
+	},
+}
+
+/// Finds the projects that would be cleaned, biggest first, without cleaning them.
+///
+/// Sizes are what the configured delete mode would free, projects where it frees nothing are left out.
+fn scan(config: Config, paths: &[PathBuf], on_warning: &(dyn Fn(Error) + Sync)) -> Result<Vec<ProjectReport>> {
+	let candidates = Mutex::new(Vec::new());
+	{
+		let scanner = Cleaner::new(config, |event| match event {
+			Event::Project(report) if report.skipped.is_none() && report.warning.is_none() && report.freed() > 0 => {
+				candidates.lock().unwrap().push(*report)
+			}
+			Event::Warning(e) => on_warning(e),
+			_ => {}
+		})?;
+		scanner.process_dirs(paths)?;
 	}
 
-	let target_dir = path.join("target");
-	if !target_dir.exists() || !target_dir.is_dir() {
-		return Ok(());
+	let mut candidates = candidates.into_inner().unwrap();
+	candidates.sort_by_key(|c| Reverse(c.freed()));
+	Ok(candidates)
+}
+
+/// Orders `candidates` so that big projects untouched for long come first.
+fn rank_for_reclaim(candidates: &mut [ProjectReport]) {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+	candidates.sort_by_key(|c| {
+		let age_days = c.last_modified.map_or(0, |m| now.saturating_sub(m) / (24 * 3600));
+		Reverse(c.freed().saturating_mul(age_days + 1))
+	});
+}
+
+/// Lets the user toggle which of `candidates` to clean, returning the chosen project paths.
+fn select(candidates: &[ProjectReport]) -> Result<Vec<PathBuf>> {
+	if candidates.is_empty() {
+		eprintln!("Nothing to clean");
+		return Ok(Vec::new());
 	}
 
-	eprintln!("Cleaning {:?}", path);
+	let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+	let mut selected = vec![false; candidates.len()];
+	loop {
+		let mut msg = String::new();
+		for (i, (candidate, &sel)) in candidates.iter().zip(&selected).enumerate() {
+			let age = candidate.last_modified.map_or_else(
+				|| "?".to_string(),
+				|m| format_duration(Duration::from_secs(now.saturating_sub(m))),
+			);
+			writeln!(
+				msg,
+				"{:>4} [{}] {:>10} {:>6} ago  {:?}",
+				i + 1,
+				if sel { 'x' } else { ' ' },
+				format_size(candidate.freed()),
+				age,
+				candidate.path
+			)
+			.unwrap();
+		}
+		let total: u64 = candidates
+			.iter()
+			.zip(&selected)
+			.filter(|(_, &sel)| sel)
+			.map(|(c, _)| c.freed())
+			.sum();
+		writeln!(msg, "Selected: {}", format_size(total)).unwrap();
+		write!(
+			msg,
+			"Toggle projects by number (e.g. `1 3-5`), `a` for all, `n` for none, empty to clean, `q` to quit: "
+		)
+		.unwrap();
+		eprint!("{}", msg);
 
-	match del_mode {
-		DeleteMode::All => {
-			Command::new("cargo").args(&["clean"]).current_dir(path).output()?;
+		let mut line = String::new();
+		if stdin().lock().read_line(&mut line)? == 0 {
+			return Ok(Vec::new());
 		}
-		DeleteMode::Partial { doc, release } => {
-			if *doc {
-				Command::new("cargo")
-					.args(&["clean", "--doc"])
-					.current_dir(path)
-					.output()?;
-			}
-			if *release {
-				Command::new("cargo")
-					.args(&["clean", "--release"])
-					.current_dir(path)
-					.output()?;
+		match line.trim() {
+			"" => break,
+			"q" => return Ok(Vec::new()),
+			"a" => selected.iter_mut().for_each(|s| *s = true),
+			"n" => selected.iter_mut().for_each(|s| *s = false),
+			input => {
+				for token in input
+					.split(|c: char| c.is_whitespace() || c == ',')
+					.filter(|t| !t.is_empty())
+				{
+					match parse_range(token, candidates.len()) {
+						Some(range) => range.for_each(|i| selected[i] = !selected[i]),
+						None => eprintln!("Invalid selection '{}'", token),
+					}
+				}
 			}
 		}
 	}
-	Ok(())
+
+	Ok(candidates
+		.iter()
+		.zip(&selected)
+		.filter(|(_, &sel)| sel)
+		.map(|(c, _)| c.path.clone())
+		.collect())
+}
+
+/// Parses a 1-based project number or range like `3-5` into 0-based indices.
+fn parse_range(token: &str, len: usize) -> Option<RangeInclusive<usize>> {
+	let (start, end) = match token.split_once('-') {
+		Some((start, end)) => (start.parse::<usize>().ok()?, end.parse::<usize>().ok()?),
+		None => {
+			let n = token.parse::<usize>().ok()?;
+			(n, n)
+		}
+	};
+	if start == 0 || start > end || end > len {
+		return None;
+	}
+	Some(start - 1..=end - 1)
+}
+
+fn print_report(report: &ProjectReport, trash: bool) {
+	if let Some(reason) = &report.skipped {
+		eprintln!("Skipping {:?}: {}", report.path, reason);
+	} else if report.dry_run {
+		// Formatted up front so that concurrent reports aren't interleaved.
+		let mut msg = format!(
+			"Would clean {:?}, freeing {} of its {} target\n",
+			report.path,
+			format_size(report.freed()),
+			format_size(report.bytes_before.unwrap_or(0))
+		);
+		for command in &report.commands {
+			writeln!(msg, "	cargo {}", command.args.join(" ")).unwrap();
+		}
+		for dir in &report.removed {
+			writeln!(msg, "	remove {:?}", dir).unwrap();
+		}
+		eprint!("{}", msg);
+	} else if report.warning.is_none() && trash {
+		eprintln!(
+			"Moved {} from {:?} to the trash",
+			format_size(report.freed()),
+			report.path
+		);
+	} else if report.warning.is_none() {
+		eprintln!("Freed {} from {:?}", format_size(report.freed()), report.path);
+	}
+}
+
+fn warn(e: &Error) {
+	// Formatted up front so that concurrent warnings aren't interleaved.
+	let mut msg = format!("Warn: {}\n", e);
+	for c in e.chain().skip(1) {
+		writeln!(msg, "	at: {}", c).unwrap();
+	}
+	eprint!("{}", msg);
 }
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::{Backend, DeleteMode};
+
+/// Progress of a [`Cleaner`](crate::Cleaner).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Event {
+	/// A project is about to be cleaned.
+	Cleaning { path: PathBuf },
+	/// A build holds the `lock` of the target of the project at `path`, waiting for it to finish.
+	Waiting { path: PathBuf, lock: PathBuf },
+	/// A detected project has been handled, whether it was cleaned, skipped or failed.
+	Project(Box<ProjectReport>),
+	/// A directory or project couldn't be processed.
+	Warning(Error),
+}
+
+/// What happened to a detected project.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectReport {
+	pub path: PathBuf,
+	pub target_dir: PathBuf,
+	pub delete_mode: DeleteMode,
+	pub backend: Backend,
+	pub dry_run: bool,
+	/// Why the project was left alone, if it was.
+	pub skipped: Option<String>,
+	pub bytes_before: Option<u64>,
+	pub bytes_after: Option<u64>,
+	/// Newest modification time in the target directory, in seconds since the Unix epoch.
+	pub last_modified: Option<u64>,
+	/// `cargo clean` commands run, or that would be run on a dry run.
+	pub commands: Vec<CommandReport>,
+	/// Paths removed directly rather than by `cargo clean`, or moved to the trash, or that would be on
+	/// a dry run.
+	pub removed: Vec<PathBuf>,
+	pub warning: Option<String>,
+}
+
+impl ProjectReport {
+	/// Bytes freed from the target directory, or that would be freed on a dry run.
+	pub fn freed(&self) -> u64 {
+		match (self.bytes_before, self.bytes_after) {
+			_ if self.skipped.is_some() => 0,
+			(Some(before), Some(after)) => before.saturating_sub(after),
+			_ => 0,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandReport {
+	pub args: Vec<String>,
+	/// `None` if the command was terminated by a signal, or not run.
+	pub exit_status: Option<i32>,
+}
+
+/// Totals of a [`Cleaner`](crate::Cleaner) run.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+	/// Bytes freed, or that would be freed on a dry run.
+	pub freed: u64,
+	/// Projects whose cleaning failed.
+	pub failed: Vec<PathBuf>,
+}
@@ -0,0 +1,282 @@
+//! Finding artifacts built by other toolchains than the ones to keep, or not used for a while.
+//!
+//! Cargo records a hash of the `rustc` version in the fingerprint of every unit it builds, under
+//! `target/<profile>/.fingerprint/<name>-<hash>/*.json`. The artifacts of a unit share its `<hash>`
+//! suffix in `deps` and `build`. A unit is removed as a whole so that cargo notices it is gone and
+//! rebuilds it.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+
+/// Toolchains listed by `rustup toolchain list`, or an empty list if rustup isn't installed.
+pub fn installed_toolchains() -> Result<Vec<String>> {
+	let output = match Command::new("rustup").args(["toolchain", "list"]).output() {
+		Ok(output) => output,
+		Err(_) => return Ok(Vec::new()),
+	};
+	if !output.status.success() {
+		bail!("`rustup toolchain list` failed ({})", output.status);
+	}
+
+	Ok(String::from_utf8_lossy(&output.stdout)
+		.lines()
+		.filter_map(|l| l.split_whitespace().next())
+		.filter(|t| *t != "no")
+		.map(String::from)
+		.collect())
+}
+
+/// Fingerprint hash of the `rustc` of `toolchain`, or of the default toolchain if `None`.
+///
+/// The hash is read back from a throwaway project built with that toolchain, since its computation
+/// is internal to cargo.
+pub fn rustc_hash(toolchain: Option<&str>) -> Result<u64> {
+	// The toolchain name is only passed to cargo, never made part of the path removed afterwards.
+	let dir = tempfile::Builder::new()
+		.prefix("cargo-clean-recursive-")
+		.tempdir()
+		.context("creating a temporary directory")?;
+	build_probe(dir.path(), toolchain).with_context(|| format!("probing toolchain {}", toolchain.unwrap_or("default")))
+}
+
+fn build_probe(dir: &Path, toolchain: Option<&str>) -> Result<u64> {
+	fs::create_dir_all(dir.join("src"))?;
+	fs::write(
+		dir.join("Cargo.toml"),
+		"[package]\nname = \"probe\"\nversion = \"0.0.0\"\n\n[workspace]\n",
+	)?;
+	fs::write(dir.join("src").join("lib.rs"), "")?;
+
+	let mut command = Command::new("cargo");
+	if let Some(toolchain) = toolchain {
+		command.arg(format!("+{}", toolchain));
+	}
+	let output = command
+		.args(["build", "--quiet"])
+		.current_dir(dir)
+		.env("CARGO_TARGET_DIR", dir.join("target"))
+		.output()?;
+	if !output.status.success() {
+		bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+	}
+
+	let fingerprints = dir.join("target").join("debug").join(".fingerprint");
+	for unit in fingerprints.read_dir()? {
+		for file in unit?.path().read_dir()? {
+			if let Some(hash) = fingerprint_rustc(&file?.path())? {
+				return Ok(hash);
+			}
+		}
+	}
+	bail!("no fingerprint found in {:?}", fingerprints)
+}
+
+/// `rustc` hash recorded in a fingerprint file, `None` if `file` isn't one.
+fn fingerprint_rustc(file: &Path) -> Result<Option<u64>> {
+	if file.extension().is_none_or(|e| e != "json") {
+		return Ok(None);
+	}
+	let content = fs::read_to_string(file).with_context(|| format!("reading {:?}", file))?;
+	let json: serde_json::Value = serde_json::from_str(&content).with_context(|| format!("parsing {:?}", file))?;
+	Ok(json.get("rustc").and_then(|r| r.as_u64()))
+}
+
+/// Fingerprints and artifacts in `target_dir` built by a `rustc` whose hash isn't in `keep`.
+pub(crate) fn stale_paths(target_dir: &Path, keep: &[u64]) -> Result<Vec<PathBuf>> {
+	let mut paths = Vec::new();
+	for profile in profile_dirs(target_dir)? {
+		let mut stale = HashSet::new();
+		for unit in profile.join(".fingerprint").read_dir()? {
+			let unit = unit?.path();
+			let mut hashes = Vec::new();
+			for file in unit.read_dir()? {
+				hashes.extend(fingerprint_rustc(&file?.path())?);
+			}
+			if !hashes.is_empty() && hashes.iter().all(|h| !keep.contains(h)) {
+				if let Some(hash) = unit.file_name().and_then(|n| n.to_str()).and_then(unit_hash) {
+					stale.insert(hash.to_string());
+				}
+				paths.push(unit);
+			}
+		}
+		paths.extend(artifacts(&profile, &stale)?);
+	}
+	Ok(paths)
+}
+
+/// Fingerprints and artifacts of the units in `target_dir` whose files in `deps` were all last
+/// used before `cutoff`.
+///
+/// A file counts as used when it was read (its access time) or written, so units still get
+/// trimmed on file systems mounted with `noatime`, only later.
+pub(crate) fn unused_paths(target_dir: &Path, cutoff: SystemTime) -> Result<Vec<PathBuf>> {
+	let mut paths = Vec::new();
+	for profile in profile_dirs(target_dir)? {
+		let deps = profile.join("deps");
+		if !deps.is_dir() {
+			continue;
+		}
+		let mut used = HashSet::new();
+		let mut unused = HashSet::new();
+		for e in deps.read_dir()? {
+			let e = e?;
+			let name = e.file_name();
+			let hash = match name.to_str().and_then(unit_hash) {
+				Some(hash) => hash.to_string(),
+				None => continue,
+			};
+			let metadata = e.metadata()?;
+			let modified = metadata.modified()?;
+			let last_use = metadata.accessed().map_or(modified, |a| a.max(modified));
+			if last_use < cutoff {
+				unused.insert(hash);
+			} else {
+				used.insert(hash);
+			}
+		}
+		unused.retain(|h| !used.contains(h));
+		if unused.is_empty() {
+			continue;
+		}
+
+		let fingerprints = profile.join(".fingerprint");
+		if fingerprints.is_dir() {
+			for e in fingerprints.read_dir()? {
+				let unit = e?.path();
+				let hash = unit.file_name().and_then(|n| n.to_str()).and_then(unit_hash);
+				if hash.is_some_and(|h| unused.contains(h)) {
+					paths.push(unit);
+				}
+			}
+		}
+		paths.extend(artifacts(&profile, &unused)?);
+	}
+	Ok(paths)
+}
+
+/// Entries of `deps` and `build` in `profile` belonging to one of the `units`.
+fn artifacts(profile: &Path, units: &HashSet<String>) -> Result<Vec<PathBuf>> {
+	let mut paths = Vec::new();
+	if units.is_empty() {
+		return Ok(paths);
+	}
+	for sub in &["deps", "build"] {
+		let dir = profile.join(sub);
+		if !dir.is_dir() {
+			continue;
+		}
+		for e in dir.read_dir()? {
+			let path = e?.path();
+			let hash = path.file_name().and_then(|n| n.to_str()).and_then(unit_hash);
+			if hash.is_some_and(|h| units.contains(h)) {
+				paths.push(path);
+			}
+		}
+	}
+	Ok(paths)
+}
+
+/// Profile directories of `target_dir`, for the host and for cross-compilation targets.
+fn profile_dirs(target_dir: &Path) -> Result<Vec<PathBuf>> {
+	let mut dirs = Vec::new();
+	for e in target_dir.read_dir()? {
+		let dir = e?.path();
+		if !dir.is_dir() {
+			continue;
+		}
+		if dir.join(".fingerprint").is_dir() {
+			dirs.push(dir);
+			continue;
+		}
+		for e in dir.read_dir()? {
+			let sub = e?.path();
+			if sub.join(".fingerprint").is_dir() {
+				dirs.push(sub);
+			}
+		}
+	}
+	Ok(dirs)
+}
+
+/// Unit hash of an artifact or fingerprint name, e.g. `4804af185fe98f35` for `libfoo-4804af185fe98f35.rlib`.
+fn unit_hash(name: &str) -> Option<&str> {
+	let stem = name.split('.').next()?;
+	let (_, hash) = stem.rsplit_once('-')?;
+	if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+		Some(hash)
+	} else {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unused_units_are_kept_while_any_dep_is_used() {
+		let tmp = tempfile::tempdir().unwrap();
+		let target_dir = tmp.path().canonicalize().unwrap();
+		let debug = target_dir.join("debug");
+		let (stale, used) = ("aaaaaaaaaaaaaaaa", "bbbbbbbbbbbbbbbb");
+		for unit in [stale, used] {
+			fs::create_dir_all(debug.join(".fingerprint").join(format!("lib-{}", unit))).unwrap();
+			fs::create_dir_all(debug.join("build").join(format!("lib-{}", unit))).unwrap();
+		}
+		fs::create_dir_all(debug.join("deps")).unwrap();
+		let month_ago = SystemTime::now() - std::time::Duration::from_secs(30 * 24 * 3600);
+		let files = [
+			(format!("liblib-{}.rlib", stale), month_ago),
+			(format!("lib-{}.d", stale), month_ago),
+			(format!("liblib-{}.rlib", used), month_ago),
+			(format!("liblib-{}.rmeta", used), SystemTime::now()),
+			// Not a unit, never removed.
+			("lib-not-a-hash.d".to_string(), month_ago),
+		];
+		for (name, time) in &files {
+			let file = fs::File::create(debug.join("deps").join(name)).unwrap();
+			let times = fs::FileTimes::new().set_accessed(*time).set_modified(*time);
+			file.set_times(times).unwrap();
+		}
+
+		let cutoff = SystemTime::now() - std::time::Duration::from_secs(24 * 3600);
+		let mut paths = unused_paths(&target_dir, cutoff).unwrap();
+		paths.sort();
+		let expected = [
+			debug.join(".fingerprint").join(format!("lib-{}", stale)),
+			debug.join("build").join(format!("lib-{}", stale)),
+			debug.join("deps").join(format!("lib-{}.d", stale)),
+			debug.join("deps").join(format!("liblib-{}.rlib", stale)),
+		];
+		assert_eq!(paths, expected);
+	}
+
+	#[test]
+	fn finds_unit_hashes() {
+		assert_eq!(unit_hash("libserde-0123456789abcdef.rlib"), Some("0123456789abcdef"));
+		assert_eq!(unit_hash("serde_json-0123456789ABCDEF.d"), Some("0123456789ABCDEF"));
+		assert_eq!(
+			unit_hash("build-script-build-0123456789abcdef"),
+			Some("0123456789abcdef")
+		);
+		assert_eq!(
+			unit_hash("app-0123456789abcdef.app.1a2b3c-cgu.0.rcgu.o"),
+			Some("0123456789abcdef")
+		);
+	}
+
+	#[test]
+	fn ignores_names_without_unit_hash() {
+		assert_eq!(unit_hash("app"), None);
+		assert_eq!(unit_hash("libapp.rlib"), None);
+		assert_eq!(unit_hash("my-app"), None);
+		assert_eq!(unit_hash("app-0123456789abcde.d"), None);
+		assert_eq!(unit_hash("app-0123456789abcdeg"), None);
+		assert_eq!(unit_hash(".fingerprint"), None);
+	}
+}
@@ -0,0 +1,230 @@
+use std::env::var_os;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Resolves the directory cargo builds `project` into, with the same precedence as cargo:
+/// the `CARGO_TARGET_DIR` environment variable, then `build.target-dir` from the nearest
+/// `.cargo/config.toml` up the directory hierarchy or in `CARGO_HOME`, then `target`.
+pub(crate) fn resolve_target_dir(project: &Path) -> Result<PathBuf> {
+	resolve_target_dir_with(project, |var| var_os(var))
+}
+
+/// [`resolve_target_dir`], reading the environment variables through `env`.
+fn resolve_target_dir_with(project: &Path, env: impl Fn(&str) -> Option<OsString>) -> Result<PathBuf> {
+	let project = project.canonicalize()?;
+
+	for var in &["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"] {
+		if let Some(dir) = env(var) {
+			return Ok(project.join(dir));
+		}
+	}
+
+	let mut config_dirs: Vec<PathBuf> = project.ancestors().map(|d| d.join(".cargo")).collect();
+	if let Some(cargo_home) = cargo_home(&env) {
+		if !config_dirs.contains(&cargo_home) {
+			config_dirs.push(cargo_home);
+		}
+	}
+
+	for config_dir in config_dirs {
+		for name in &["config", "config.toml"] {
+			let config_file = config_dir.join(name);
+			if !config_file.is_file() {
+				continue;
+			}
+
+			let content = fs::read_to_string(&config_file).with_context(|| format!("reading {:?}", config_file))?;
+			let config: toml::Value = content.parse().with_context(|| format!("parsing {:?}", config_file))?;
+			if let Some(dir) = config
+				.get("build")
+				.and_then(|b| b.get("target-dir"))
+				.and_then(|d| d.as_str())
+			{
+				// Relative paths are relative to the directory containing `.cargo`.
+				let base = config_dir.parent().unwrap_or(&config_dir);
+				return Ok(base.join(dir));
+			}
+			break;
+		}
+	}
+
+	Ok(workspace_root(&project).join("target"))
+}
+
+/// The root of the workspace `project` is a member of, or `project` itself.
+fn workspace_root(project: &Path) -> PathBuf {
+	for dir in project.ancestors().skip(1) {
+		// Broken manifests up the hierarchy shouldn't prevent cleaning `project`.
+		if let Ok(members) = workspace_members(dir) {
+			if members.iter().any(|m| m == project) {
+				return dir.to_path_buf();
+			}
+		}
+	}
+	project.to_path_buf()
+}
+
+/// Directories of the members of the workspace rooted at `path`, empty if `path` isn't a workspace root.
+pub(crate) fn workspace_members(path: &Path) -> Result<Vec<PathBuf>> {
+	let manifest_path = path.join("Cargo.toml");
+	if !manifest_path.is_file() {
+		return Ok(Vec::new());
+	}
+
+	let content = fs::read_to_string(&manifest_path).with_context(|| format!("reading {:?}", manifest_path))?;
+	let manifest: toml::Value = content
+		.parse()
+		.with_context(|| format!("parsing {:?}", manifest_path))?;
+	let workspace = match manifest.get("workspace") {
+		Some(workspace) => workspace,
+		None => return Ok(Vec::new()),
+	};
+
+	let paths = |key: &str| -> Vec<PathBuf> {
+		workspace
+			.get(key)
+			.and_then(|v| v.as_array())
+			.map(|a| {
+				a.iter()
+					.filter_map(|v| v.as_str())
+					.map(|p| normalize(&path.join(p)))
+					.collect()
+			})
+			.unwrap_or_default()
+	};
+	let excluded = paths("exclude");
+
+	let mut members = Vec::new();
+	for pattern in paths("members") {
+		let pattern = pattern
+			.to_str()
+			.with_context(|| format!("non UTF-8 member path {:?}", pattern))?;
+		for dir in glob::glob(pattern).with_context(|| format!("invalid member pattern '{}'", pattern))? {
+			let dir = normalize(&dir?);
+			if dir.join("Cargo.toml").is_file() && !excluded.contains(&dir) {
+				members.push(dir);
+			}
+		}
+	}
+	Ok(members)
+}
+
+/// Removes `.` components so that paths built differently compare equal.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+	path.components().collect()
+}
+
+fn cargo_home(env: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+	if let Some(home) = env("CARGO_HOME") {
+		return Some(PathBuf::from(home));
+	}
+	env("HOME")
+		.or_else(|| env("USERPROFILE"))
+		.map(|home| PathBuf::from(home).join(".cargo"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn write(path: &Path, content: &str) {
+		fs::create_dir_all(path.parent().unwrap()).unwrap();
+		fs::write(path, content).unwrap();
+	}
+
+	fn write_package(dir: &Path) {
+		write(
+			&dir.join("Cargo.toml"),
+			"[package]\nname = \"p\"\nversion = \"0.1.0\"\n",
+		);
+	}
+
+	/// Resolves with only `vars` set, and a `CARGO_HOME` without configuration.
+	fn resolve(project: &Path, vars: &[(&str, &str)]) -> PathBuf {
+		let cargo_home = project.join("no-cargo-home");
+		let env = |var: &str| match vars.iter().find(|(k, _)| *k == var) {
+			Some((_, v)) => Some(OsString::from(v)),
+			None if var == "CARGO_HOME" => Some(cargo_home.clone().into_os_string()),
+			None => None,
+		};
+		resolve_target_dir_with(project, env).unwrap()
+	}
+
+	#[test]
+	fn env_var_takes_precedence_over_config() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path().canonicalize().unwrap();
+		let project = root.join("p");
+		write_package(&project);
+		write(
+			&project.join(".cargo").join("config.toml"),
+			"[build]\ntarget-dir = \"from-config\"\n",
+		);
+
+		assert_eq!(
+			resolve(&project, &[("CARGO_TARGET_DIR", "/tmp/env")]),
+			Path::new("/tmp/env")
+		);
+		assert_eq!(resolve(&project, &[("CARGO_TARGET_DIR", "rel")]), project.join("rel"));
+		assert_eq!(resolve(&project, &[]), project.join("from-config"));
+	}
+
+	#[test]
+	fn nearest_config_wins_relative_to_its_parent() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path().canonicalize().unwrap();
+		let project = root.join("a").join("p");
+		write_package(&project);
+		write(
+			&root.join(".cargo").join("config.toml"),
+			"[build]\ntarget-dir = \"outer\"\n",
+		);
+		write(
+			&root.join("a").join(".cargo").join("config"),
+			"[build]\ntarget-dir = \"../inner\"\n",
+		);
+
+		assert_eq!(resolve(&project, &[]), root.join("a").join("..").join("inner"));
+		// A configuration without `build.target-dir` doesn't hide the ones further up.
+		write(&project.join(".cargo").join("config.toml"), "[net]\nretry = 3\n");
+		assert_eq!(resolve(&project, &[]), root.join("a").join("..").join("inner"));
+	}
+
+	#[test]
+	fn members_resolve_to_the_workspace_target() {
+		let tmp = tempfile::tempdir().unwrap();
+		let ws = tmp.path().canonicalize().unwrap();
+		write(
+			&ws.join("Cargo.toml"),
+			"[workspace]\nmembers = [\"crates/*\", \"./tool\"]\nexclude = [\"crates/skip\"]\n",
+		);
+		for member in ["crates/a", "crates/b", "crates/skip", "tool"] {
+			write_package(&ws.join(member));
+		}
+		fs::create_dir_all(ws.join("crates").join("not-a-crate")).unwrap();
+
+		let mut members = workspace_members(&ws).unwrap();
+		members.sort();
+		let expected = [ws.join("crates/a"), ws.join("crates/b"), ws.join("tool")];
+		assert_eq!(members, expected);
+
+		assert_eq!(workspace_root(&ws.join("crates/a")), ws);
+		assert_eq!(resolve(&ws.join("crates/a"), &[]), ws.join("target"));
+		assert_eq!(resolve(&ws.join("tool"), &[]), ws.join("target"));
+		assert_eq!(resolve(&ws.join("crates/skip"), &[]), ws.join("crates/skip/target"));
+	}
+
+	#[test]
+	fn packages_without_workspace_have_no_members() {
+		let tmp = tempfile::tempdir().unwrap();
+		let project = tmp.path().canonicalize().unwrap();
+		assert!(workspace_members(&project).unwrap().is_empty());
+		write_package(&project);
+		assert!(workspace_members(&project).unwrap().is_empty());
+		write(&project.join("Cargo.toml"), "[workspace\n");
+		assert!(workspace_members(&project).is_err());
+	}
+}
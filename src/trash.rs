@@ -0,0 +1,369 @@
+//! Moving paths to a trash directory instead of deleting them, and putting them back.
+//!
+//! Trash directories follow the [freedesktop.org specification]: a trashed path is moved to
+//! `files/<name>`, and `info/<name>.trashinfo` records where it came from and when.
+//!
+//! [freedesktop.org specification]: https://specifications.freedesktop.org/trash-spec/latest/
+
+use std::env::var_os;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use crate::disk::device;
+use crate::target_dir::resolve_target_dir;
+
+/// The home trash of the user, `$XDG_DATA_HOME/Trash` (`~/.local/share/Trash` by default).
+pub fn default_dir() -> Option<PathBuf> {
+	let data_dir = match var_os("XDG_DATA_HOME") {
+		Some(dir) => PathBuf::from(dir),
+		None => PathBuf::from(var_os("HOME")?).join(".local").join("share"),
+	};
+	Some(data_dir.join("Trash"))
+}
+
+/// The trash `path` is moved to: `trash_dir` if it is on the same filesystem, since paths can't be
+/// moved across filesystems, or else `$topdir/.Trash-$uid` at the top of the filesystem of `path`.
+pub(crate) fn dir_for(trash_dir: &Path, path: &Path) -> Result<PathBuf> {
+	let path = canonical(path)?;
+	let existing = |p: &Path| p.ancestors().find(|a| a.exists()).map(Path::to_path_buf);
+	let trash_device = match existing(trash_dir) {
+		Some(dir) => device(&dir)?,
+		None => return Ok(trash_dir.to_path_buf()),
+	};
+	let mut top = existing(&path).unwrap_or_else(|| path.clone());
+	let path_device = device(&top)?;
+	if path_device.is_none() || path_device == trash_device {
+		return Ok(trash_dir.to_path_buf());
+	}
+	while let Some(parent) = top.parent() {
+		if device(parent)? != path_device {
+			break;
+		}
+		top = parent.to_path_buf();
+	}
+	Ok(top.join(format!(".Trash-{}", uid())))
+}
+
+/// Moves `path` to `home_trash`, or to the trash of its filesystem, see [`dir_for`]. A symlink is
+/// moved itself, not what it points to.
+pub(crate) fn move_to(home_trash: &Path, path: &Path) -> Result<()> {
+	let path = canonical(path)?;
+	let trash_dir = dir_for(home_trash, &path)?;
+	if trash_dir != home_trash {
+		check_topdir_trash(&trash_dir)?;
+	}
+	let files = trash_dir.join("files");
+	let info = trash_dir.join("info");
+	fs::create_dir_all(&files).with_context(|| format!("creating {:?}", files))?;
+	fs::create_dir_all(&info).with_context(|| format!("creating {:?}", info))?;
+
+	let base = path.file_name().map_or("target".into(), |n| n.to_string_lossy());
+	// The info file is created first, exclusively, to reserve the name.
+	let (name, info_file) = (1..)
+		.map(|i| match i {
+			1 => base.to_string(),
+			_ => format!("{}.{}", base, i),
+		})
+		.find_map(|name| {
+			let file = info.join(format!("{}.trashinfo", name));
+			match OpenOptions::new().write(true).create_new(true).open(&file) {
+				Ok(_) if files.join(&name).exists() => {
+					let _ = fs::remove_file(&file);
+					None
+				}
+				Ok(_) => Some(Ok((name, file))),
+				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => None,
+				Err(e) => Some(Err(e).with_context(|| format!("creating {:?}", file))),
+			}
+		})
+		.expect("names are unbounded")?;
+
+	let result = write_info(&info_file, &path).and_then(|()| {
+		fs::rename(&path, files.join(&name)).with_context(|| format!("moving {:?} to {:?}", path, trash_dir))
+	});
+	if result.is_err() {
+		let _ = fs::remove_file(&info_file);
+	}
+	result
+}
+
+/// Creates the `.Trash-$uid` directory if needed, making sure an existing one belongs to the user
+/// rather than, say, being a symlink planted by someone else on a shared filesystem.
+#[cfg(unix)]
+fn check_topdir_trash(trash_dir: &Path) -> Result<()> {
+	use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+	match trash_dir.symlink_metadata() {
+		Ok(meta) if meta.is_dir() && meta.uid() == uid() => Ok(()),
+		Ok(_) => bail!("{:?} is not a trash directory of the current user", trash_dir),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => fs::DirBuilder::new()
+			.mode(0o700)
+			.create(trash_dir)
+			.with_context(|| format!("creating {:?}", trash_dir)),
+		Err(e) => Err(e).with_context(|| format!("reading metadata of {:?}", trash_dir)),
+	}
+}
+
+#[cfg(not(unix))]
+fn check_topdir_trash(_trash_dir: &Path) -> Result<()> {
+	Ok(())
+}
+
+#[cfg(unix)]
+fn uid() -> u32 {
+	// SAFETY: getuid has no preconditions and can't fail.
+	unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn uid() -> u32 {
+	0
+}
+
+/// `path` with its existing ancestors resolved, without following `path` itself if it is a symlink.
+fn canonical(path: &Path) -> Result<PathBuf> {
+	let path = std::path::absolute(path)?;
+	let mut rest = Vec::new();
+	let mut dir = path.as_path();
+	while let Some(parent) = dir.parent() {
+		rest.push(dir.file_name().unwrap_or_default());
+		if parent.exists() {
+			let mut canonical = parent.canonicalize()?;
+			canonical.extend(rest.iter().rev());
+			return Ok(canonical);
+		}
+		dir = parent;
+	}
+	Ok(path)
+}
+
+fn write_info(info_file: &Path, path: &Path) -> Result<()> {
+	let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+	let mut file = fs::File::create(info_file)?;
+	write!(
+		file,
+		"[Trash Info]\nPath={}\nDeletionDate={}\n",
+		encode(&path.to_string_lossy()),
+		format_date(secs)
+	)
+	.with_context(|| format!("writing {:?}", info_file))
+}
+
+/// Paths put back by [`restore`].
+#[derive(Debug, Default)]
+pub struct Restored {
+	pub restored: Vec<PathBuf>,
+	/// Paths left in the trash because something was built there since.
+	pub existing: Vec<PathBuf>,
+}
+
+/// Moves the most recently trashed version of each path of the target of `project` back into
+/// place, from `trash_dir` or, for a target on another filesystem, from the `.Trash-$uid` directory
+/// at the top of that filesystem.
+///
+/// Paths that exist again are left in the trash. Fails if nothing was restored.
+pub fn restore(trash_dir: &Path, project: &Path) -> Result<Restored> {
+	let project = project
+		.canonicalize()
+		.with_context(|| format!("resolving {:?}", project))?;
+	let target_dir = resolve_target_dir(&project).context("resolving target directory")?;
+	// Trashed paths are recorded canonicalized, the target may be reached through a symlink.
+	let target_dir = match target_dir.canonicalize() {
+		Ok(dir) => dir,
+		Err(_) => canonical(&target_dir)?,
+	};
+
+	let mut trash_dirs = vec![trash_dir.to_path_buf()];
+	let topdir_trash = dir_for(trash_dir, &target_dir)?;
+	if topdir_trash != trash_dir {
+		trash_dirs.push(topdir_trash);
+	}
+
+	let mut entries: Vec<(PathBuf, String, SystemTime, PathBuf, String)> = Vec::new();
+	for trash_dir in &trash_dirs {
+		let info = trash_dir.join("info");
+		let dir = match info.read_dir() {
+			Ok(dir) => dir,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+			Err(e) => return Err(e).with_context(|| format!("reading {:?}", info)),
+		};
+		for e in dir {
+			let file = e?.path();
+			let name = match file
+				.file_name()
+				.and_then(|n| n.to_str())
+				.and_then(|n| n.strip_suffix(".trashinfo"))
+			{
+				Some(name) => name.to_string(),
+				None => continue,
+			};
+			let content = fs::read_to_string(&file).with_context(|| format!("reading {:?}", file))?;
+			let field = |key: &str| content.lines().find_map(|l| l.strip_prefix(key)).map(String::from);
+			let (original, date) = match (field("Path="), field("DeletionDate=")) {
+				(Some(original), Some(date)) => (PathBuf::from(decode(&original)), date),
+				_ => continue,
+			};
+			if original.starts_with(&target_dir) {
+				// Dates only have a precision of a second, the info file tells apart paths trashed within one.
+				let modified = file.metadata().and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+				entries.push((original, date, modified, trash_dir.clone(), name));
+			}
+		}
+	}
+	// Restores the parents first, newest first for each path.
+	entries.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| (&b.1, b.2).cmp(&(&a.1, a.2))));
+
+	// Checked up front, as older versions of a path are left behind once the newest is restored.
+	let mut result = Restored::default();
+	let mut originals: Vec<&PathBuf> = entries.iter().map(|e| &e.0).collect();
+	originals.dedup();
+	result.existing = originals.into_iter().filter(|p| p.exists()).cloned().collect();
+
+	for (original, _, _, trash_dir, name) in &entries {
+		if original.exists() {
+			continue;
+		}
+		if let Some(parent) = original.parent() {
+			fs::create_dir_all(parent).with_context(|| format!("creating {:?}", parent))?;
+		}
+		let trashed = trash_dir.join("files").join(name);
+		fs::rename(&trashed, original).with_context(|| format!("moving {:?} to {:?}", trashed, original))?;
+		fs::remove_file(trash_dir.join("info").join(format!("{}.trashinfo", name)))?;
+		result.restored.push(original.clone());
+	}
+	if result.restored.is_empty() {
+		if !result.existing.is_empty() {
+			let mut msg = String::new();
+			for path in &result.existing {
+				msg.push_str(&format!("\n\t{:?}", path));
+			}
+			bail!(
+				"nothing to restore for {:?}, these paths exist again, move them aside first:{}",
+				project,
+				msg
+			);
+		}
+		bail!("nothing to restore for {:?} in {:?}", project, trash_dirs);
+	}
+	Ok(result)
+}
+
+/// Percent-encodes `path` as the specification requires.
+fn encode(path: &str) -> String {
+	let mut encoded = String::new();
+	for b in path.bytes() {
+		if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+			encoded.push(b as char);
+		} else {
+			encoded.push_str(&format!("%{:02X}", b));
+		}
+	}
+	encoded
+}
+
+fn decode(encoded: &str) -> String {
+	let bytes = encoded.as_bytes();
+	let mut decoded = Vec::new();
+	let mut i = 0;
+	while i < bytes.len() {
+		let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+		match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+			(b'%', Some(b)) => {
+				decoded.push(b);
+				i += 3;
+			}
+			(b, _) => {
+				decoded.push(b);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `YYYY-MM-DDThh:mm:ss` for `secs` since the Unix epoch, in UTC rather than the local time the
+/// specification asks for, which would need a time zone database.
+fn format_date(secs: u64) -> String {
+	let days = (secs / 86400) as i64;
+	let rem = secs % 86400;
+	// Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html.
+	let z = days + 719_468;
+	let era = z.div_euclid(146_097);
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+	format!(
+		"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+		year,
+		month,
+		day,
+		rem / 3600,
+		rem % 3600 / 60,
+		rem % 60
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encodes_paths() {
+		assert_eq!(encode("/home/me/my project/target"), "/home/me/my%20project/target");
+		assert_eq!(encode("/a%b/é"), "/a%25b/%C3%A9");
+		assert_eq!(encode("/x-y_z.~"), "/x-y_z.~");
+	}
+
+	#[test]
+	fn decodes_paths() {
+		for path in ["/home/me/my project/target", "/a%b/é", "/100%/x"] {
+			assert_eq!(decode(&encode(path)), path);
+		}
+		assert_eq!(decode("/a%2"), "/a%2");
+		assert_eq!(decode("/a%zz"), "/a%zz");
+	}
+
+	#[test]
+	fn formats_dates() {
+		assert_eq!(format_date(0), "1970-01-01T00:00:00");
+		assert_eq!(format_date(951_782_400), "2000-02-29T00:00:00");
+		assert_eq!(format_date(1_700_000_000), "2023-11-14T22:13:20");
+		assert_eq!(format_date(4_107_542_399), "2100-02-28T23:59:59");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn restores_over_symlinked_targets_and_reports_rebuilt_paths() -> Result<()> {
+		let tmp = tempfile::tempdir()?;
+		let (project, real, trash) = (tmp.path().join("p"), tmp.path().join("real"), tmp.path().join("trash"));
+		fs::create_dir_all(&project)?;
+		fs::write(
+			project.join("Cargo.toml"),
+			"[package]\nname = \"p\"\nversion = \"0.1.0\"\n",
+		)?;
+		fs::create_dir_all(real.join("debug"))?;
+		std::os::unix::fs::symlink(&real, project.join("target"))?;
+
+		move_to(&trash, &project.join("target").join("debug"))?;
+		assert!(!project.join("target").join("debug").exists());
+
+		fs::create_dir(project.join("target").join("debug"))?;
+		let err = restore(&trash, &project).unwrap_err();
+		assert!(err.to_string().contains("exist again"));
+
+		fs::remove_dir(project.join("target").join("debug"))?;
+		let result = restore(&trash, &project)?;
+		assert_eq!(result.restored.len(), 1);
+		assert!(result.existing.is_empty());
+		assert!(project.join("target").join("debug").is_dir());
+		Ok(())
+	}
+}
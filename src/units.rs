@@ -0,0 +1,135 @@
+//! Parsing and formatting of durations and sizes for humans.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+const DURATION_UNITS: [(char, u64); 5] = [('w', 7 * 24 * 3600), ('d', 24 * 3600), ('h', 3600), ('m', 60), ('s', 1)];
+
+/// Parses durations like `30d`, `12h` or `90m`. A bare number is taken as days.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+	let s = s.trim();
+	let (num, secs) = match s.chars().last() {
+		Some(c) if c.is_ascii_alphabetic() => {
+			let &(_, secs) = DURATION_UNITS
+				.iter()
+				.find(|(u, _)| *u == c.to_ascii_lowercase())
+				.with_context(|| format!("unknown duration unit '{}'", c))?;
+			(&s[..s.len() - 1], secs)
+		}
+		_ => (s, 24 * 3600),
+	};
+	let num: u64 = num.parse()?;
+	match num.checked_mul(secs) {
+		Some(secs) => Ok(Duration::from_secs(secs)),
+		None => bail!("duration '{}' is too long", s),
+	}
+}
+
+/// Formats `d` in its largest whole unit, e.g. `3d`.
+pub fn format_duration(d: Duration) -> String {
+	let secs = d.as_secs();
+	for &(unit, unit_secs) in &DURATION_UNITS {
+		if secs >= unit_secs {
+			return format!("{}{}", secs / unit_secs, unit);
+		}
+	}
+	format!("{}s", secs)
+}
+
+/// Parses sizes like `10G`, `500MiB` or `1.5GB`. Units are powers of 1024, a bare number is in bytes.
+pub fn parse_size(s: &str) -> Result<u64> {
+	let s = s.trim();
+	let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+	let (num, unit) = s.split_at(split);
+	let shift = match unit.to_ascii_lowercase().as_str() {
+		"" | "b" => 0,
+		"k" | "kb" | "kib" => 10,
+		"m" | "mb" | "mib" => 20,
+		"g" | "gb" | "gib" => 30,
+		"t" | "tb" | "tib" => 40,
+		_ => bail!("unknown size unit '{}'", unit),
+	};
+	let num: f64 = num.trim().parse()?;
+	if num < 0.0 {
+		bail!("negative size");
+	}
+	let bytes = num * (1u64 << shift) as f64;
+	if bytes >= u64::MAX as f64 {
+		bail!("size '{}' is too large", s);
+	}
+	Ok(bytes as u64)
+}
+
+/// Formats `bytes` with binary units, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+	const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+	if bytes < 1024 {
+		return format!("{} B", bytes);
+	}
+
+	let mut size = bytes as f64 / 1024.0;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_durations() {
+		assert_eq!(parse_duration("30d").unwrap(), Duration::from_secs(30 * 24 * 3600));
+		assert_eq!(parse_duration("2w").unwrap(), Duration::from_secs(14 * 24 * 3600));
+		assert_eq!(parse_duration(" 12H ").unwrap(), Duration::from_secs(12 * 3600));
+		assert_eq!(parse_duration("90m").unwrap(), Duration::from_secs(90 * 60));
+		assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+		assert_eq!(parse_duration("3").unwrap(), Duration::from_secs(3 * 24 * 3600));
+	}
+
+	#[test]
+	fn rejects_invalid_durations() {
+		assert!(parse_duration("").is_err());
+		assert!(parse_duration("10y").is_err());
+		assert!(parse_duration("-1d").is_err());
+		assert!(parse_duration("1.5d").is_err());
+		assert!(parse_duration("99999999999999999w").is_err());
+		assert!(parse_duration(&format!("{}s", u64::MAX)).is_ok());
+	}
+
+	#[test]
+	fn formats_durations() {
+		assert_eq!(format_duration(Duration::from_secs(3 * 24 * 3600 + 5)), "3d");
+		assert_eq!(format_duration(Duration::from_secs(59)), "59s");
+		assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+	}
+
+	#[test]
+	fn parses_sizes() {
+		assert_eq!(parse_size("512").unwrap(), 512);
+		assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+		assert_eq!(parse_size("500MiB").unwrap(), 500 << 20);
+		assert_eq!(parse_size("1.5GB").unwrap(), 3 << 29);
+		assert_eq!(parse_size("2 t").unwrap(), 2 << 40);
+	}
+
+	#[test]
+	fn rejects_invalid_sizes() {
+		assert!(parse_size("").is_err());
+		assert!(parse_size("10X").is_err());
+		assert!(parse_size("-1G").is_err());
+		assert!(parse_size("99999999999T").is_err());
+	}
+
+	#[test]
+	fn formats_sizes() {
+		assert_eq!(format_size(1023), "1023 B");
+		assert_eq!(format_size(1536), "1.5 KiB");
+		assert_eq!(format_size(3 << 29), "1.5 GiB");
+	}
+}